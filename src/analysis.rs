@@ -0,0 +1,213 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::{env, fs};
+use rust_stemmers::{Algorithm, Stemmer};
+use sha2::{Digest, Sha256};
+use crate::tokenizer::{fold_diacritics, tokenize_spans, FOLD_DIACRITICS};
+
+/// Language used to analyze texts when none is known.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Revision of the way texts are analyzed, to increase when words of a same
+/// text would be analyzed differently, see `ANALYSIS_VERSION`.
+const ANALYSIS_REVISION: u32 = 1;
+
+lazy_static! {
+    /// Directory holding stopword lists, one `{language}.txt` file per
+    /// language with a word per line. Set by `STOPWORDS_DIR`.
+    static ref STOPWORDS_DIR: String = env::var("STOPWORDS_DIR")
+        .unwrap_or("./stopwords".into());
+    /// Languages with a stopword list in `STOPWORDS_DIR`.
+    static ref STOPWORD_LANGUAGES: HashSet<String> = fs::read_dir(
+        STOPWORDS_DIR.as_str()
+    )
+        .map(|dir| dir
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter_map(|name| Some(name.strip_suffix(".txt")?.to_string()))
+            .collect())
+        .unwrap_or_default();
+    /// Version of the analysis of texts, from `ANALYSIS_REVISION`,
+    /// `FOLD_DIACRITICS` and the stopword lists. Sites indexed with another
+    /// version are indexed again, even if they didn't change.
+    pub static ref ANALYSIS_VERSION: String = {
+        let mut hasher = Sha256::new();
+        let mut languages = STOPWORD_LANGUAGES.iter().collect::<Vec<_>>();
+
+        languages.sort();
+        hasher.update(format!("{}", *FOLD_DIACRITICS));
+        for language in languages {
+            let path = format!("{}/{language}.txt", *STOPWORDS_DIR);
+
+            hasher.update(language);
+            hasher.update(fs::read(path).unwrap_or_default());
+        }
+        let hash = format!("{:x}", hasher.finalize());
+
+        format!("{ANALYSIS_REVISION}-{}", &hash[..16])
+    };
+    /// Analyzers are built once per language, as loading stopwords is costly.
+    /// Only known languages get one, see `is_known_language`.
+    static ref ANALYZERS: Mutex<HashMap<String, Arc<Analyzer>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Returns the primary language of a localization, `fr-CA` gives `fr`.
+pub fn primary_language(loc: &str) -> String {
+    loc.split(['-', '_']).next().unwrap_or("").trim().to_lowercase()
+}
+
+/// Returns the stemming algorithm of a language, if there is one.
+fn stemmer_algorithm(language: &str) -> Option<Algorithm> {
+    match language {
+        "ar" => Some(Algorithm::Arabic),
+        "da" => Some(Algorithm::Danish),
+        "de" => Some(Algorithm::German),
+        "el" => Some(Algorithm::Greek),
+        "en" => Some(Algorithm::English),
+        "es" => Some(Algorithm::Spanish),
+        "fr" => Some(Algorithm::French),
+        "hu" => Some(Algorithm::Hungarian),
+        "it" => Some(Algorithm::Italian),
+        "nl" => Some(Algorithm::Dutch),
+        "no" | "nb" | "nn" => Some(Algorithm::Norwegian),
+        "pt" => Some(Algorithm::Portuguese),
+        "ro" => Some(Algorithm::Romanian),
+        "ru" => Some(Algorithm::Russian),
+        "sv" => Some(Algorithm::Swedish),
+        "ta" => Some(Algorithm::Tamil),
+        "tr" => Some(Algorithm::Turkish),
+        _ => None
+    }
+}
+
+/// Returns true if a language has a stemmer or a stopword list.
+fn is_known_language(language: &str) -> bool {
+    stemmer_algorithm(language).is_some() ||
+        STOPWORD_LANGUAGES.contains(language)
+}
+
+/// Reads the stopword list of a language, words are folded the same way as
+/// analyzed words. A missing list gives no stopwords.
+fn load_stopwords(language: &str) -> HashSet<String> {
+    let path = format!("{}/{language}.txt", *STOPWORDS_DIR);
+
+    match fs::read_to_string(&path) {
+        Ok(list) => list.lines()
+            .map(|l| l.split('#').next().unwrap().trim())
+            .filter(|l| !l.is_empty())
+            .map(|l| fold_diacritics(&l.to_lowercase()))
+            .collect(),
+        Err(_) => {
+            println!("[ANALYSIS] No stopwords for `{language}` at {path}");
+            HashSet::new()
+        }
+    }
+}
+
+/// Turns texts into the words stored in the index, or looked up in it. Texts
+/// are split by `tokenizer::tokenize_spans`, then stopwords are filtered out,
+/// and words are reduced to their stem, `indexing` and `indexed` both give
+/// `index`. Stemming and stopwords depend on the language of the text.
+/// INFO: Sites are analyzed with their language, and queries with the one
+/// they are searched in. A site and a query in different languages may not
+/// have the same words.
+pub struct Analyzer {
+    pub language: String,
+    stemmer: Option<Stemmer>,
+    stopwords: HashSet<String>
+}
+
+impl std::fmt::Debug for Analyzer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Analyzer")
+            .field("language", &self.language)
+            .field("stemmer", &self.stemmer.is_some())
+            .field("stopwords", &self.stopwords.len())
+            .finish()
+    }
+}
+
+impl Analyzer {
+    /// Returns the analyzer of the language of a localization, such as `en-US`.
+    /// A language without stemmer nor stopword list gets the analyzer of
+    /// `DEFAULT_LANGUAGE`.
+    pub fn for_language(loc: &str) -> Arc<Self> {
+        let language = match primary_language(loc) {
+            l if is_known_language(&l) => l,
+            _ => DEFAULT_LANGUAGE.to_string()
+        };
+        let mut analyzers = ANALYZERS.lock().unwrap();
+
+        analyzers.entry(language.clone())
+            .or_insert_with(|| Arc::new(Self {
+                stemmer: stemmer_algorithm(&language).map(Stemmer::create),
+                stopwords: load_stopwords(&language),
+                language
+            }))
+            .clone()
+    }
+
+    /// Analyzes a single word, `None` is returned for stopwords.
+    fn analyze_word(&self, word: &str) -> Option<String> {
+        if self.stopwords.contains(&fold_diacritics(word)) {
+            return None;
+        }
+        match &self.stemmer {
+            Some(stemmer) => Some(fold_diacritics(&stemmer.stem(word))),
+            None => Some(fold_diacritics(word))
+        }
+    }
+
+    /// Splits a text into analyzed words, each word comes with its byte range
+    /// in the text. Stopwords are `None`, those are kept so that the position
+    /// of each word in the text is known.
+    pub fn analyze_spans(
+        &self,
+        text: &str
+    ) -> Vec<(Option<String>, Range<usize>)> {
+        tokenize_spans(text)
+            .into_iter()
+            .map(|(word, range)| (self.analyze_word(&word), range))
+            .collect()
+    }
+
+    /// Splits a text into analyzed words, see `analyze_spans`.
+    pub fn analyze(&self, text: &str) -> Vec<Option<String>> {
+        self.analyze_spans(text).into_iter().map(|(word, _)| word).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_languages_get_the_default_analyzer() {
+        assert_eq!(Analyzer::for_language("fr-CA").language, "fr");
+        assert_eq!(Analyzer::for_language("it").language, "it");
+        assert_eq!(Analyzer::for_language("xx").language, DEFAULT_LANGUAGE);
+        assert_eq!(
+            Analyzer::for_language("../../etc/passwd").language,
+            DEFAULT_LANGUAGE
+        );
+        assert!(!ANALYZERS.lock().unwrap().contains_key("xx"));
+    }
+
+    #[test]
+    fn words_are_stemmed_by_language() {
+        let en = Analyzer::for_language("en-US");
+        let fr = Analyzer::for_language("fr");
+
+        assert_eq!(
+            en.analyze("The indexed pages"),
+            [None, Some("index".into()), Some("page".into())]
+        );
+        assert_eq!(en.analyze("Indexing"), en.analyze("indexes"));
+        assert_eq!(
+            fr.analyze("Les écoles"),
+            [None, Some("ecol".into())]
+        );
+        assert_ne!(en.analyze("running"), fr.analyze("running"));
+    }
+}
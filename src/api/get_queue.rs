@@ -11,7 +11,7 @@ pub struct IndexSysStatus {
 #[get("/index_sys_status")]
 pub fn get_index_sys_status() -> Json<IndexSysStatus> {
     Json(IndexSysStatus {
-        queue_length: QUEUE_BOT.get_remaining_urls().len(),
+        queue_length: QUEUE_BOT.queue_length(),
         indexed_urls: unsafe { INDEXED_URLS_NB }
     })
 }
@@ -1,2 +1,3 @@
 pub mod get_queue;
 pub mod ownership;
+pub mod sitemaps;
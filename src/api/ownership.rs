@@ -1,10 +1,10 @@
 use chrono::{DateTime, Utc};
-use rocket::{http::Status, response::status, serde::json::Json};
+use rocket::{http::Status, serde::json::Json};
 use serde_derive::{Deserialize, Serialize};
 use trust_dns_resolver::TokioAsyncResolver;
 use rocket_db_pools::Connection;
 use url::{ParseError, Url};
-use crate::{db::{domains::{get_domain_ownership_record, update_domain_ownership_record}, jwt_auth::AuthFromJWT, sites::{get_all_sites_records_of_a_domain, SiteRecord}}, Pg, QUEUE_BOT};
+use crate::{db::{frontier::FrontierEntry, domains::{get_domain_ownership_record, update_domain_ownership_record}, jwt_auth::AuthFromJWT, sites::{get_all_sites_records_of_a_domain, SiteRecord}}, Pg, QUEUE_BOT};
 
 #[derive(Serialize, Deserialize)]
 pub struct ResOwnershipVerification {
@@ -45,7 +45,7 @@ fn extract_domain_from_str(from: String) -> Result<String, ParseError> {
         }
     }
 
-    return Ok(url.unwrap().domain().unwrap().to_string()); 
+    Ok(url.unwrap().domain().unwrap().to_string())
 }
 
 /// *First reference to the concept of domain ownership.*
@@ -104,9 +104,9 @@ pub async fn check_domain_ownership(
         if txt.to_string() == ownership_rec {
             update_domain_ownership_record(pg, domain.clone(), uid)
                 .await.unwrap();
-            QUEUE_BOT.queue_url(vec![domain]);
+            QUEUE_BOT.queue_url(vec![FrontierEntry::seed(domain)]);
 
-            let queue_position = QUEUE_BOT.get_remaining_urls().len();
+            let queue_position = QUEUE_BOT.queue_length();
 
             return Json(ResOwnershipVerification {
                 queue_position,
@@ -157,7 +157,7 @@ pub async fn get_domain_analytics(
 
     if let Ok(pages) = get_all_sites_records_of_a_domain(domain.clone()) {
         return Ok(Json(ResAnalyticsData {
-            domain: domain,
+            domain,
             owned_by: auth.from_claims.user_id,
             created_at: record.created_at,
             indexed_pages: pages
@@ -167,7 +167,7 @@ pub async fn get_domain_analytics(
 }
 
 // TODO: make something meaningful of this
-#[options("/domain/get_analytics?<domain>")]
-pub async fn get_domain_analytics_preflight(domain: String) -> Status {
+#[options("/domain/get_analytics")]
+pub async fn get_domain_analytics_preflight() -> Status {
     Status::Accepted
 }
@@ -0,0 +1,32 @@
+use rocket::{http::Status, serde::json::Json};
+use rocket_db_pools::Connection;
+use crate::db::{domains::get_domain_ownership_record, jwt_auth::AuthFromJWT};
+use crate::db::sitemaps::{get_sitemaps_of_domain, SitemapRecord};
+use crate::Pg;
+
+/// Lists the sitemaps of a domain and of its subdomains, with the result of
+/// their last fetch and when they'll be read again.
+/// INFO: Fetch errors and sitemap URLs may be private, so only the owner of
+/// the domain can list its sitemaps, see `get_domain_analytics`.
+#[get("/sitemaps?<domain>")]
+pub async fn get_domain_sitemaps(
+    pg: Connection<Pg>,
+    auth: AuthFromJWT,
+    domain: String
+) -> Result<Json<Vec<SitemapRecord>>, Status> {
+    if !auth.verified {
+        return Err(Status::Unauthorized);
+    }
+
+    let domain = domain.trim().to_lowercase();
+    let record = get_domain_ownership_record(pg, domain.clone()).await
+        .map_err(|_| Status::Forbidden)?;
+
+    if record.owned_by.to_string() != auth.from_claims.user_id {
+        return Err(Status::Forbidden);
+    }
+
+    get_sitemaps_of_domain(&domain)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
@@ -0,0 +1,202 @@
+use rusqlite::OptionalExtension;
+use crate::indexer::alternates::{cluster_key, Alternate};
+use crate::DB_POOL;
+
+/// Initializes the table if it doesn't exists already. This table holds the
+/// alternates declared by each indexed page, the versions of the page in
+/// other languages.
+/// INFO: Alternates are linked to pages by URL and not by site id as those
+/// are often declared before being indexed.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS alternates (
+            url TEXT NOT NULL,
+            alternate TEXT NOT NULL,
+            hreflang TEXT NOT NULL,
+            PRIMARY KEY (url, alternate, hreflang)
+        )
+    ", [])?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS alternates_alternate ON alternates (alternate)",
+        []
+    )?;
+    Ok(())
+}
+
+/// Replaces the alternates declared by a page and saves the cluster the page
+/// belongs to. Only alternates declaring the page in return are part of its
+/// cluster, a page can't join the cluster of a page that doesn't declare it.
+/// INFO: Clusters of the page and of its alternates are merged, alternates are
+/// often indexed at different times, so that each version of a page ends up in
+/// the same cluster.
+pub fn save_alternates(
+    url: &String,
+    alternates: &[Alternate]
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let tx = conn.transaction()?;
+
+    tx.execute("DELETE FROM alternates WHERE url = ?1", params![url])?;
+    for alternate in alternates {
+        tx.execute("
+            INSERT OR IGNORE INTO alternates (url, alternate, hreflang)
+            VALUES (?1, ?2, ?3)
+        ", params![url, alternate.url, alternate.hreflang])?;
+    }
+
+    // Alternates declaring the page in return, as their own alternate.
+    let confirmed = {
+        let mut select = tx.prepare("
+            SELECT DISTINCT a.alternate, a.hreflang
+            FROM alternates AS a
+            WHERE a.url = ?1 AND a.alternate != ?1 AND EXISTS (
+                SELECT 1 FROM alternates AS b
+                WHERE b.url = a.alternate AND b.alternate = ?1
+            )
+        ")?;
+        let confirmed = select
+            .query_map(params![url], |row| Ok(Alternate {
+                url: row.get(0)?,
+                hreflang: row.get(1)?
+            }))?
+            .collect::<Result<Vec<Alternate>, _>>()?;
+
+        confirmed
+    };
+
+    let Some(mut cluster) = cluster_key(url, &confirmed) else {
+        tx.execute(
+            "UPDATE sites SET cluster = NULL WHERE url = ?1", params![url]
+        )?;
+        tx.commit()?;
+        return Ok(());
+    };
+    let members = confirmed.iter()
+        .map(|a| &a.url)
+        .chain([url])
+        .collect::<Vec<&String>>();
+    let mut merged: Vec<String> = vec![];
+
+    for member in &members {
+        let previous = tx
+            .query_row(
+                "SELECT cluster FROM sites WHERE url = ?1",
+                params![member],
+                |row| row.get::<usize, Option<String>>(0)
+            )
+            .optional()?
+            .flatten();
+
+        if let Some(previous) = previous {
+            cluster = cluster.min(previous.clone());
+            merged.push(previous);
+        }
+    }
+    for member in members {
+        tx.execute(
+            "UPDATE sites SET cluster = ?1 WHERE url = ?2",
+            params![cluster, member]
+        )?;
+    }
+    for previous in merged {
+        tx.execute(
+            "UPDATE sites SET cluster = ?1 WHERE cluster = ?2",
+            params![cluster, previous]
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Deletes the alternates declared by a page.
+pub fn delete_alternates(
+    url: &String
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("DELETE FROM alternates WHERE url = ?1", params![url])?;
+    Ok(())
+}
+
+/// Returns the alternates of a list that aren't indexed yet.
+pub fn filter_unindexed(
+    alternates: &[Alternate]
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let mut select = conn.prepare("SELECT 1 FROM sites WHERE url = ?1")?;
+    let mut urls: Vec<String> = vec![];
+
+    for alternate in alternates {
+        let indexed = select.exists(params![alternate.url])?;
+
+        if !indexed && !urls.contains(&alternate.url) {
+            urls.push(alternate.url.clone());
+        }
+    }
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_test_tables, new_test_site};
+
+    fn cluster_of(url: &str) -> Option<String> {
+        let conn = DB_POOL.clone().get().unwrap();
+
+        conn.query_row(
+            "SELECT cluster FROM sites WHERE url = ?1",
+            params![url],
+            |row| row.get(0)
+        ).unwrap()
+    }
+
+    fn alternate(hreflang: &str, url: &str) -> Alternate {
+        Alternate { hreflang: hreflang.into(), url: url.into() }
+    }
+
+    #[test]
+    fn reciprocal_alternates_share_a_cluster() {
+        init_test_tables();
+        let en = "http://mutual.test/en".to_string();
+        let fr = "http://mutual.test/fr".to_string();
+        let declared = [alternate("en", &en), alternate("fr", &fr)];
+
+        new_test_site(&en, "", "");
+        new_test_site(&fr, "", "");
+        save_alternates(&en, &declared).unwrap();
+        assert_eq!(cluster_of(&en), None);
+
+        save_alternates(&fr, &declared).unwrap();
+        assert_eq!(cluster_of(&en), Some(en.clone()));
+        assert_eq!(cluster_of(&fr), Some(en.clone()));
+    }
+
+    #[test]
+    fn one_way_alternates_dont_change_clusters() {
+        init_test_tables();
+        let victim_en = "http://victim.test/en".to_string();
+        let victim_fr = "http://victim.test/fr".to_string();
+        let attacker = "http://attacker.test/".to_string();
+        let declared = [
+            alternate("en", &victim_en),
+            alternate("fr", &victim_fr)
+        ];
+
+        new_test_site(&victim_en, "", "");
+        new_test_site(&victim_fr, "", "");
+        new_test_site(&attacker, "", "");
+        save_alternates(&victim_en, &declared).unwrap();
+        save_alternates(&victim_fr, &declared).unwrap();
+        save_alternates(&attacker, &[
+            alternate("de", &attacker),
+            alternate("en", &victim_en)
+        ]).unwrap();
+
+        assert_eq!(cluster_of(&victim_en), Some(victim_en.clone()));
+        assert_eq!(cluster_of(&victim_fr), Some(victim_en.clone()));
+        assert_eq!(cluster_of(&attacker), None);
+    }
+}
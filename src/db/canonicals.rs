@@ -0,0 +1,55 @@
+use rusqlite::OptionalExtension;
+use crate::DB_POOL;
+
+/// Initializes the table if it doesn't exists already. This table links the
+/// URLs of pages declaring another canonical URL to it, those pages are only
+/// indexed under their canonical URL.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS canonicals (
+            url TEXT PRIMARY KEY,
+            canonical TEXT NOT NULL
+        )
+    ", [])?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS canonicals_canonical ON canonicals (canonical)",
+        []
+    )?;
+    Ok(())
+}
+
+/// Saves the canonical URL of a page, a page that is its own canonical URL
+/// forgets any previous one.
+pub fn save_canonical(
+    url: &String,
+    canonical: &String
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    if url == canonical {
+        conn.execute("DELETE FROM canonicals WHERE url = ?1", params![url])?;
+    } else {
+        conn.execute("
+            INSERT OR REPLACE INTO canonicals (url, canonical) VALUES (?1, ?2)
+        ", params![url, canonical])?;
+    }
+    Ok(())
+}
+
+/// Returns the canonical URL a page was found to declare, if it isn't its own.
+pub fn get_canonical_of(
+    url: &String
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let canonical = conn
+        .query_row(
+            "SELECT canonical FROM canonicals WHERE url = ?1",
+            params![url],
+            |row| row.get::<usize, String>(0)
+        )
+        .optional()?;
+
+    Ok(canonical)
+}
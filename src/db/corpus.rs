@@ -0,0 +1,57 @@
+use rusqlite::Connection;
+use crate::DB_POOL;
+
+/// Initalizes the table if it doesn't exists already. This table only has a
+/// single row holding statistics about every indexed site, those are needed
+/// to rank results.
+/// INFO: Statistics are recomputed from the `sites` table on startup to make
+/// up for any drift.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS corpus (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            documents INTEGER NOT NULL,
+            total_length INTEGER NOT NULL
+        );
+        INSERT OR REPLACE INTO corpus (id, documents, total_length)
+        SELECT 0, COUNT(1), COALESCE(SUM(length), 0) FROM sites;
+    ")?;
+    Ok(())
+}
+
+/// Applies a change to the corpus statistics.
+/// INFO: A connection is taken to allow this function to be used inside of
+/// a transaction.
+pub fn update(
+    conn: &Connection,
+    documents_delta: i64,
+    length_delta: i64
+) -> Result<(), rusqlite::Error> {
+    conn.execute("
+        UPDATE corpus
+        SET documents = documents + ?1, total_length = total_length + ?2
+        WHERE id = 0
+    ", params![documents_delta, length_delta])?;
+    Ok(())
+}
+
+/// Returns the number of indexed documents and their average length.
+pub fn get_stats(conn: &Connection) -> Result<(i64, f64), rusqlite::Error> {
+    conn.query_row(
+        "SELECT documents, total_length FROM corpus WHERE id = 0",
+        [],
+        |row| {
+            let documents = row.get::<usize, i64>(0)?;
+            let total_length = row.get::<usize, i64>(1)?;
+            let avg_length = if documents > 0 {
+                total_length as f64 / documents as f64
+            } else {
+                0.0
+            };
+
+            Ok((documents, avg_length))
+        }
+    )
+}
@@ -2,13 +2,14 @@ use std::{collections::HashMap, str::FromStr};
 use crate::{models::{AddDomainOwnership, DomainOwnershipRecord}, schemas::_public::domains};
 use rocket_db_pools::Connection;
 use uuid::Uuid;
-use crate::{sanitize::{sql_encode_uas, sql_escape_ap}, Pg, DB_POOL};
+use crate::{db::{add_missing_columns, escape_like}, sanitize::sql_encode_uas, Pg, DB_POOL};
 use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
 
 // WARN: IMPORTANT NOTICE FOR THIS TABLE
 // The `uas_allow` and `uas_disallow` columns are stringified hashmaps, those
 // must be encoded and decoded using the `sql_encode_uas` and `sql_decode_uas`
-// functions from the `sanitize` module.
+// functions from the `sanitize` module. So is the `crawl_delays` column, where
+// each User-Agent has a single value.
 
 /// Initalizes the table if it doesn't exists already.
 pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
@@ -21,14 +22,17 @@ pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
             last_ownership_check INTEGER,
             uas_allow TEXT,
             uas_disallow TEXT,
-            owned_by_uid TEXT
+            owned_by_uid TEXT,
+            crawl_delays TEXT
         )
     ", [])?;
+    add_missing_columns(&conn, "domains", &[("crawl_delays", "TEXT")])?;
     Ok(())
 }
 
 /// Saves data of a domain to the database.
 /// WARN: If a row for this domain already exists, every value get updated.
+#[allow(dead_code)]
 pub fn create_row(
     domain: String,
     last_robots_txt_visit: u128,
@@ -38,11 +42,8 @@ pub fn create_row(
     owned_by_uid: String
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = DB_POOL.clone().get().unwrap();
-    let domain = sql_escape_ap(domain);
-    let uas_allow = sql_escape_ap(sql_encode_uas(uas_allow));
-    let uas_disallow = sql_escape_ap(sql_encode_uas(uas_disallow));
 
-    conn.execute(&format!("
+    conn.execute("
         INSERT OR REPLACE INTO domains (
             domain,
             last_robots_txt_visit,
@@ -51,15 +52,15 @@ pub fn create_row(
             uas_disallow,
             owned_by_uid
         )
-        VALUES (
-            '{domain}',
-            {last_robots_txt_visit},
-            {last_ownership_check},
-            '{uas_allow}',
-            '{uas_disallow}',
-            '{owned_by_uid}'
-        )
-    "), [])?;
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ", params![
+        domain,
+        last_robots_txt_visit as i64,
+        last_ownership_check as i64,
+        sql_encode_uas(uas_allow),
+        sql_encode_uas(uas_disallow),
+        owned_by_uid
+    ])?;
     Ok(())
 }
 
@@ -74,12 +75,9 @@ pub fn create_row_iff_empty(
     owned_by_uid: String
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = DB_POOL.clone().get().unwrap();
-    let domain = sql_escape_ap(domain);
-    let uas_allow = sql_escape_ap(sql_encode_uas(uas_allow));
-    let uas_disallow = sql_escape_ap(sql_encode_uas(uas_disallow));
 
-    conn.execute(&format!("
-        INSERT OR REPLACE INTO domains (
+    conn.execute("
+        INSERT OR IGNORE INTO domains (
             domain,
             last_robots_txt_visit,
             last_ownership_check,
@@ -87,15 +85,52 @@ pub fn create_row_iff_empty(
             uas_disallow,
             owned_by_uid
         )
-        VALUES (
-            '{domain}',
-            {last_robots_txt_visit},
-            {last_ownership_check},
-            '{uas_allow}',
-            '{uas_disallow}',
-            '{owned_by_uid}'
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ", params![
+        domain,
+        last_robots_txt_visit as i64,
+        last_ownership_check as i64,
+        sql_encode_uas(uas_allow),
+        sql_encode_uas(uas_disallow),
+        owned_by_uid
+    ])?;
+    Ok(())
+}
+
+/// Saves the robots rules of a domain, the row of the domain is created if it
+/// doesn't exist. Ownership data of the domain is kept.
+pub fn update_robots(
+    domain: String,
+    last_robots_txt_visit: u128,
+    uas_allow: HashMap<String, Vec<String>>,
+    uas_disallow: HashMap<String, Vec<String>>,
+    crawl_delays: HashMap<String, Vec<String>>
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        INSERT INTO domains (
+            domain,
+            last_robots_txt_visit,
+            last_ownership_check,
+            uas_allow,
+            uas_disallow,
+            owned_by_uid,
+            crawl_delays
         )
-    "), [])?;
+        VALUES (?1, ?2, 0, ?3, ?4, 'null', ?5)
+        ON CONFLICT (domain) DO UPDATE SET
+            last_robots_txt_visit = excluded.last_robots_txt_visit,
+            uas_allow = excluded.uas_allow,
+            uas_disallow = excluded.uas_disallow,
+            crawl_delays = excluded.crawl_delays
+    ", params![
+        domain,
+        last_robots_txt_visit as i64,
+        sql_encode_uas(uas_allow),
+        sql_encode_uas(uas_disallow),
+        sql_encode_uas(crawl_delays)
+    ])?;
     Ok(())
 }
 
@@ -109,18 +144,17 @@ pub async fn update_domain_ownership_record(
     owned_by: String
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = DB_POOL.clone().get().unwrap();
-    let domain = sql_escape_ap(domain);
 
     let new_ownership = AddDomainOwnership {
         domain: domain.clone(),
         owned_by: Uuid::from_str(&owned_by)?
     };
 
-    conn.execute(&format!("
+    conn.execute("
         UPDATE domains
-        SET owned_by_uid = '{owned_by}'
-        WHERE domain LIKE '%{domain}'
-    "), [])?;
+        SET owned_by_uid = ?1
+        WHERE domain = ?2 OR domain LIKE '%.' || ?3 ESCAPE '\\'
+    ", params![owned_by, domain, escape_like(&domain)])?;
 
     async {
         use rocket_db_pools::diesel::prelude::RunQueryDsl;
@@ -157,7 +191,7 @@ pub async fn get_domain_ownership_record(
         return Err(());
     }
 
-    let out = domains_list.get(0).unwrap();
+    let out = domains_list[0];
 
     // TODO: wtf do I have to .clone().clone() ??????
     Ok((*out).clone())
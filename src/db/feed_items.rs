@@ -0,0 +1,54 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::DB_POOL;
+
+/// Initializes the table if it doesn't exists already. This table holds the
+/// links of the items of each feed, to only queue the items that are new.
+/// INFO: Items that left a feed are forgotten, so that the table doesn't grow
+/// with the feed.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS feed_items (
+            feed TEXT NOT NULL,
+            link TEXT NOT NULL,
+            seen_at INTEGER NOT NULL,
+            PRIMARY KEY (feed, link)
+        )
+    ", [])?;
+    Ok(())
+}
+
+/// Replaces the items of a feed, and returns the links that weren't seen
+/// before.
+pub fn replace_items(
+    feed: &String,
+    links: &[String]
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let tx = conn.transaction()?;
+    let mut new_links = vec![];
+
+    {
+        let mut select = tx.prepare(
+            "SELECT 1 FROM feed_items WHERE feed = ?1 AND link = ?2"
+        )?;
+
+        for link in links {
+            if !select.exists(params![feed, link])? {
+                new_links.push(link.clone());
+            }
+            tx.execute("
+                INSERT OR REPLACE INTO feed_items (feed, link, seen_at)
+                VALUES (?1, ?2, ?3)
+            ", params![feed, link, now])?;
+        }
+    }
+    tx.execute(
+        "DELETE FROM feed_items WHERE feed = ?1 AND seen_at < ?2",
+        params![feed, now]
+    )?;
+    tx.commit()?;
+    Ok(new_links)
+}
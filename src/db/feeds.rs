@@ -0,0 +1,110 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::OptionalExtension;
+use crate::DB_POOL;
+
+/// A feed to poll, as saved on the database. Times are in milliseconds since
+/// the UNIX epoch.
+pub struct FeedRecord {
+    pub url: String,
+    pub poll_interval: Duration,
+    pub next_poll_at: i64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>
+}
+
+/// Initializes the table if it doesn't exists already. This table holds the
+/// RSS and Atom feeds discovered on indexed pages, with the page they were
+/// found on, and when they must be polled again.
+/// INFO: The poll interval is in seconds, it follows the update rate of the
+/// feed, see `FeedDefinition::next_interval`.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS feeds (
+            url TEXT PRIMARY KEY,
+            source TEXT NOT NULL,
+            poll_interval INTEGER NOT NULL,
+            next_poll_at INTEGER NOT NULL,
+            last_polled_at INTEGER,
+            etag TEXT,
+            last_modified TEXT
+        )
+    ", [])?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS feeds_next_poll_at ON feeds (next_poll_at)",
+        []
+    )?;
+    Ok(())
+}
+
+/// Returns the current time in milliseconds since the UNIX epoch.
+fn now() -> Result<i64, Box<dyn std::error::Error>> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64)
+}
+
+/// Saves a feed discovered on a page, it's polled as soon as possible.
+/// Returns true if the feed wasn't known yet.
+pub fn save_feed(
+    url: &String,
+    source: &String,
+    poll_interval: Duration
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let inserted = conn.execute("
+        INSERT OR IGNORE INTO feeds (url, source, poll_interval, next_poll_at)
+        VALUES (?1, ?2, ?3, ?4)
+    ", params![url, source, poll_interval.as_secs() as i64, now()?])?;
+
+    Ok(inserted > 0)
+}
+
+/// Returns the feed that must be polled first, `None` if there is no feed.
+pub fn get_next_feed(
+) -> Result<Option<FeedRecord>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let feed = conn
+        .query_row("
+            SELECT url, poll_interval, next_poll_at, etag, last_modified
+            FROM feeds
+            ORDER BY next_poll_at
+            LIMIT 1
+        ", [], |row| Ok(FeedRecord {
+            url: row.get(0)?,
+            poll_interval: Duration::from_secs(row.get::<usize, u64>(1)?),
+            next_poll_at: row.get(2)?,
+            etag: row.get(3)?,
+            last_modified: row.get(4)?
+        }))
+        .optional()?;
+
+    Ok(feed)
+}
+
+/// Saves that a feed was polled, and schedules its next poll. The validators
+/// of the response are kept to poll the feed conditionally.
+pub fn update_poll(
+    url: &String,
+    poll_interval: Duration,
+    etag: Option<String>,
+    last_modified: Option<String>
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let now = now()?;
+
+    conn.execute("
+        UPDATE feeds
+        SET poll_interval = ?1, next_poll_at = ?2, last_polled_at = ?3,
+            etag = COALESCE(?4, etag),
+            last_modified = COALESCE(?5, last_modified)
+        WHERE url = ?6
+    ", params![
+        poll_interval.as_secs() as i64,
+        now + poll_interval.as_millis() as i64,
+        now,
+        etag,
+        last_modified,
+        url
+    ])?;
+    Ok(())
+}
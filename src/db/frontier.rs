@@ -0,0 +1,385 @@
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::{params_from_iter, OptionalExtension, ToSql};
+use url::Url;
+use crate::DB_POOL;
+
+lazy_static! {
+    /// Time crawled URLs are kept in the frontier, set in days with
+    /// `FRONTIER_RETENTION`, 30 days by default. `0` keeps them forever.
+    pub static ref FRONTIER_RETENTION: Option<Duration> =
+        env::var("FRONTIER_RETENTION").ok()
+            .and_then(|d| d.parse::<u64>().ok())
+            .or(Some(30))
+            .filter(|days| *days > 0)
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+}
+
+/// Where a queued URL comes from, it sets how soon the URL is crawled:
+/// - `Seed`: Submitted to the indexer, crawled first.
+/// - `Sitemap`: Listed by a sitemap, ranked by its `<priority>` and
+///   `<changefreq>`.
+/// - `Feed`: Linked by a new item of a feed.
+/// - `Link`: Discovered on a crawled page, crawled last.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueSource {
+    Seed,
+    Sitemap,
+    Feed,
+    Link
+}
+
+impl QueueSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Seed => "seed",
+            Self::Sitemap => "sitemap",
+            Self::Feed => "feed",
+            Self::Link => "link"
+        }
+    }
+
+    /// Returns the source saved as `source`, unknown sources are links.
+    fn from_str(source: &str) -> Self {
+        match source {
+            "seed" => Self::Seed,
+            "sitemap" => Self::Sitemap,
+            "feed" => Self::Feed,
+            _ => Self::Link
+        }
+    }
+}
+
+/// A URL of the crawl frontier. The depth is the number of links followed from
+/// a seed, a sitemap or a feed to find the URL.
+/// INFO: URLs are crawled by decreasing priority, then by increasing depth:
+/// seeds have a priority of 4, sitemap URLs from 2 to 3 following their
+/// `<priority>` and `<changefreq>`, feed items 2.5 and links 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrontierEntry {
+    pub url: String,
+    pub source: QueueSource,
+    pub priority: f64,
+    pub depth: u32
+}
+
+impl FrontierEntry {
+    pub fn seed(url: String) -> Self {
+        Self { url, source: QueueSource::Seed, priority: 4., depth: 0 }
+    }
+
+    /// A URL listed by a sitemap, with its crawl priority from 0 to 1, see
+    /// `SitemapEntry::crawl_priority`.
+    /// INFO: The priority is rounded as it's parsed as a `f32`.
+    pub fn sitemap(url: String, priority: f32) -> Self {
+        let priority = (priority.clamp(0., 1.) as f64 * 1000.).round() / 1000.;
+
+        Self {
+            url,
+            source: QueueSource::Sitemap,
+            priority: 2. + priority,
+            depth: 0
+        }
+    }
+
+    pub fn feed(url: String) -> Self {
+        Self { url, source: QueueSource::Feed, priority: 2.5, depth: 0 }
+    }
+
+    pub fn link(url: String, depth: u32) -> Self {
+        Self { url, source: QueueSource::Link, priority: 1., depth }
+    }
+}
+
+/// Returns the host of a URL, invalid URLs share the empty host.
+pub fn url_host(url: &str) -> String {
+    Url::parse(url).ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_default()
+}
+
+/// Initializes the table if it doesn't exists already. This table holds the
+/// URLs to crawl and the URLs that were crawled, so that each URL is queued
+/// once. URLs are crawled once they're scheduled for, times are in
+/// milliseconds since the UNIX epoch.
+/// INFO: The index of pending URLs is in crawl order and also holds their
+/// schedule and host, so that `get_next` filters them without reading the
+/// table, and stops at the first URL that can be crawled.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS frontier (
+            url TEXT PRIMARY KEY,
+            host TEXT NOT NULL,
+            priority REAL NOT NULL,
+            depth INTEGER NOT NULL,
+            source TEXT NOT NULL,
+            queued_at INTEGER NOT NULL,
+            scheduled_for INTEGER NOT NULL,
+            crawled_at INTEGER
+        )
+    ", [])?;
+    // Replaced by `frontier_queue`, which also holds the schedule and host.
+    conn.execute("DROP INDEX IF EXISTS frontier_pending", [])?;
+    conn.execute("
+        CREATE INDEX IF NOT EXISTS frontier_queue
+        ON frontier (priority DESC, depth, queued_at, scheduled_for, host)
+        WHERE crawled_at IS NULL
+    ", [])?;
+    Ok(())
+}
+
+/// Returns the current time in milliseconds since the UNIX epoch.
+fn now() -> Result<i64, Box<dyn std::error::Error>> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64)
+}
+
+/// Returns `?` placeholders for the given number of parameters.
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+/// Queues URLs to crawl, they're crawled as soon as possible. Returns the
+/// number of URLs that were queued or updated.
+/// INFO: A URL already queued keeps its highest priority and lowest depth.
+/// A crawled URL is queued again only if it doesn't come from a link, so that
+/// pages linking to each other aren't crawled in a loop.
+pub fn push(
+    entries: &[FrontierEntry]
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let tx = conn.transaction()?;
+    let now = now()?;
+    let mut queued = 0;
+
+    {
+        let mut stmt = tx.prepare("
+            INSERT INTO frontier (
+                url, host, priority, depth, source, queued_at, scheduled_for
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+            ON CONFLICT (url) DO UPDATE SET
+                priority = CASE WHEN crawled_at IS NULL
+                    THEN max(priority, excluded.priority)
+                    ELSE excluded.priority END,
+                source = CASE
+                    WHEN crawled_at IS NULL AND priority >= excluded.priority
+                    THEN source ELSE excluded.source END,
+                depth = CASE WHEN crawled_at IS NULL
+                    THEN min(depth, excluded.depth)
+                    ELSE excluded.depth END,
+                queued_at = CASE WHEN crawled_at IS NULL
+                    THEN queued_at ELSE excluded.queued_at END,
+                scheduled_for = CASE WHEN crawled_at IS NULL
+                    THEN min(scheduled_for, excluded.scheduled_for)
+                    ELSE excluded.scheduled_for END,
+                crawled_at = NULL
+            WHERE crawled_at IS NULL OR excluded.source != 'link'
+        ")?;
+
+        for entry in entries {
+            queued += stmt.execute(params![
+                entry.url,
+                url_host(&entry.url),
+                entry.priority,
+                entry.depth,
+                entry.source.as_str(),
+                now
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(queued)
+}
+
+/// Returns the URL to crawl first among those scheduled for now, URLs of the
+/// given hosts are left out. Returns `None` if there is no URL to crawl yet.
+/// WARN: The URL stays in the frontier until it's marked as crawled, see
+/// `mark_crawled`, so it's crawled again if the indexer stops meanwhile.
+pub fn get_next(
+    excluded_hosts: &[String]
+) -> Result<Option<FrontierEntry>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let now = now()?;
+    let mut params: Vec<&dyn ToSql> = vec![&now];
+
+    params.extend(excluded_hosts.iter().map(|h| h as &dyn ToSql));
+
+    let entry = conn
+        .query_row(&format!("
+            SELECT url, source, priority, depth
+            FROM frontier
+            WHERE crawled_at IS NULL AND scheduled_for <= ?
+                AND host NOT IN ({})
+            ORDER BY priority DESC, depth, queued_at, rowid
+            LIMIT 1
+        ", placeholders(excluded_hosts.len())),
+        params_from_iter(params),
+        |row| Ok(FrontierEntry {
+            url: row.get(0)?,
+            source: QueueSource::from_str(&row.get::<usize, String>(1)?),
+            priority: row.get(2)?,
+            depth: row.get(3)?
+        }))
+        .optional()?;
+
+    Ok(entry)
+}
+
+/// Returns the time until the next URL to crawl is scheduled for, URLs of the
+/// given hosts are left out. Returns `None` if there is no URL to crawl.
+pub fn next_scheduled_in(
+    excluded_hosts: &[String]
+) -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let now = now()?;
+    let params = excluded_hosts.iter().map(|h| h as &dyn ToSql);
+    let scheduled_for: Option<i64> = conn.query_row(&format!("
+        SELECT min(scheduled_for)
+        FROM frontier
+        WHERE crawled_at IS NULL AND host NOT IN ({})
+    ", placeholders(excluded_hosts.len())), params_from_iter(params), |row| {
+        row.get(0)
+    })?;
+
+    Ok(scheduled_for.map(|at| Duration::from_millis((at - now).max(0) as u64)))
+}
+
+/// Schedules a queued URL to be crawled after a delay.
+pub fn postpone(
+    url: &String,
+    delay: Duration
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute(
+        "UPDATE frontier SET scheduled_for = ?1 WHERE url = ?2",
+        params![now()? + delay.as_millis() as i64, url]
+    )?;
+    Ok(())
+}
+
+/// Saves that a URL was crawled, whatever the outcome, it's not crawled again
+/// unless it's queued again.
+pub fn mark_crawled(url: &String) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute(
+        "UPDATE frontier SET crawled_at = ?1 WHERE url = ?2",
+        params![now()?, url]
+    )?;
+    Ok(())
+}
+
+/// Removes the URLs crawled before the given time, so that the frontier
+/// doesn't grow with every URL ever crawled. Returns the number of URLs
+/// removed.
+/// INFO: A removed URL can be queued by a link again, the page is then crawled
+/// again as if it was new.
+pub fn prune_crawled(
+    older_than: Duration
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let before = now()? - older_than.as_millis() as i64;
+
+    Ok(conn.execute(
+        "DELETE FROM frontier WHERE crawled_at < ?1",
+        params![before]
+    )?)
+}
+
+/// Returns the number of URLs waiting to be crawled.
+pub fn count_pending() -> Result<usize, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let count = conn.query_row(
+        "SELECT count(*) FROM frontier WHERE crawled_at IS NULL",
+        [],
+        |row| row.get::<usize, usize>(0)
+    )?;
+
+    Ok(count)
+}
+
+/// Removes the URLs waiting to be crawled, crawled URLs are kept. Returns the
+/// number of URLs removed.
+pub fn clear_pending() -> Result<usize, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    Ok(conn.execute("DELETE FROM frontier WHERE crawled_at IS NULL", [])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the priority, depth and source of a URL, and whether it's
+    /// waiting to be crawled.
+    fn queued(url: &str) -> (f64, u32, String, bool) {
+        let conn = DB_POOL.clone().get().unwrap();
+
+        conn.query_row("
+            SELECT priority, depth, source, crawled_at IS NULL
+            FROM frontier WHERE url = ?1
+        ", [url], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        }).unwrap()
+    }
+
+    #[test]
+    fn queued_urls_keep_their_highest_priority() {
+        crate::db::init_test_tables();
+
+        let url = "https://frontier.test/priority".to_string();
+
+        push(&[FrontierEntry::link(url.clone(), 3)]).unwrap();
+        push(&[FrontierEntry::sitemap(url.clone(), 0.5)]).unwrap();
+        assert_eq!(queued(&url), (2.5, 0, "sitemap".into(), true));
+
+        push(&[FrontierEntry::link(url.clone(), 2)]).unwrap();
+        assert_eq!(queued(&url), (2.5, 0, "sitemap".into(), true));
+
+        push(&[FrontierEntry::seed(url.clone())]).unwrap();
+        assert_eq!(queued(&url), (4., 0, "seed".into(), true));
+    }
+
+    #[test]
+    fn crawled_urls_are_only_queued_again_by_seeds_sitemaps_and_feeds() {
+        crate::db::init_test_tables();
+
+        let url = "https://frontier.test/crawled".to_string();
+
+        push(&[FrontierEntry::seed(url.clone())]).unwrap();
+        mark_crawled(&url).unwrap();
+        assert_eq!(push(&[FrontierEntry::link(url.clone(), 1)]).unwrap(), 0);
+        assert_eq!(queued(&url), (4., 0, "seed".into(), false));
+
+        assert_eq!(push(&[FrontierEntry::feed(url.clone())]).unwrap(), 1);
+        assert_eq!(queued(&url), (2.5, 0, "feed".into(), true));
+    }
+
+    #[test]
+    fn old_crawled_urls_are_pruned() {
+        crate::db::init_test_tables();
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        let urls = ["old", "recent", "pending"]
+            .map(|path| format!("https://prune.test/{path}"));
+        let conn = DB_POOL.clone().get().unwrap();
+
+        push(&urls.clone().map(FrontierEntry::seed)).unwrap();
+        mark_crawled(&urls[0]).unwrap();
+        mark_crawled(&urls[1]).unwrap();
+        conn.execute(
+            "UPDATE frontier SET crawled_at = crawled_at - ?1 WHERE url = ?2",
+            params![3 * day.as_millis() as i64, urls[0]]
+        ).unwrap();
+
+        assert_eq!(prune_crawled(2 * day).unwrap(), 1);
+        assert!(conn.query_row(
+            "SELECT url FROM frontier WHERE url = ?1", [&urls[0]], |_| Ok(())
+        ).optional().unwrap().is_none());
+        assert_eq!(queued(&urls[1]), (4., 0, "seed".into(), false));
+        assert_eq!(queued(&urls[2]), (4., 0, "seed".into(), true));
+    }
+}
@@ -60,7 +60,7 @@ impl<'r> FromRequest<'r> for AuthFromJWT {
         let secret = env::var("JWT_SECRET").expect("JWT_SECRET missing");
         let dec_key = DecodingKey::from_secret(secret.as_bytes());
         let validator = Validation::new(Algorithm::HS256);
-        let claims = decode::<AuthClaims>(&jwt, &dec_key, &validator);
+        let claims = decode::<AuthClaims>(jwt, &dec_key, &validator);
 
         if let Err(err) = claims {
             println!("AuthResult Guard: {}", err);
@@ -9,5 +9,105 @@ pub mod jwt_auth;
 // contains actions that can be done on the `sites` table.
 pub mod sites;
 pub mod domains;
-#[path = "./_[word].rs"]
-pub mod _word;
+pub mod terms;
+pub mod postings;
+pub mod corpus;
+pub mod alternates;
+pub mod skipped_urls;
+pub mod canonicals;
+pub mod redirects;
+pub mod feeds;
+pub mod feed_items;
+pub mod sitemaps;
+pub mod frontier;
+
+use rusqlite::Connection;
+
+/// Initializes the tables of the database if they don't exist already. Tables
+/// are created before the tables referring to them.
+pub fn init_tables() {
+    domains::init_table().expect("Failed to init 'domains' table.");
+    sites::init_table().expect("Failed to init 'sites' table.");
+    terms::init_table().expect("Failed to init 'terms' table.");
+    postings::init_table().expect("Failed to init 'postings' table.");
+    corpus::init_table().expect("Failed to init 'corpus' table.");
+    alternates::init_table().expect("Failed to init 'alternates' table.");
+    skipped_urls::init_table().expect("Failed to init 'skipped_urls' table.");
+    canonicals::init_table().expect("Failed to init 'canonicals' table.");
+    redirects::init_table().expect("Failed to init 'redirects' table.");
+    feeds::init_table().expect("Failed to init 'feeds' table.");
+    feed_items::init_table().expect("Failed to init 'feed_items' table.");
+    sitemaps::init_table().expect("Failed to init 'sitemaps' table.");
+    frontier::init_table().expect("Failed to init 'frontier' table.");
+}
+
+/// Adds the columns missing from an existing table, each column is given as
+/// `(name, definition)`. Returns the names of the columns that were added.
+/// INFO: This is how tables of the index database are migrated when they gain
+/// a new column.
+pub fn add_missing_columns(
+    conn: &Connection,
+    table: &str,
+    columns: &[(&str, &str)]
+) -> Result<Vec<String>, rusqlite::Error> {
+    let existing = conn
+        .prepare("SELECT name FROM pragma_table_info(?1)")?
+        .query_map(params![table], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    let mut added = vec![];
+
+    for (name, definition) in columns {
+        if existing.iter().any(|c| c == name) {
+            continue;
+        }
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {name} {definition}"), []
+        )?;
+        added.push(name.to_string());
+    }
+    Ok(added)
+}
+
+/// Escapes the `%`, `_` and `\` characters of a value matched with `LIKE`,
+/// which must be followed by `ESCAPE '\'`. Values given by users are then
+/// matched literally.
+pub fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Saves a site to the test database, after the domain it refers to.
+#[cfg(test)]
+pub fn new_test_site(url: &str, title: &str, description: &str) {
+    let domain = url::Url::parse(url).unwrap().domain().unwrap().to_string();
+
+    domains::create_row_iff_empty(
+        domain,
+        0,
+        0,
+        std::collections::HashMap::new(),
+        std::collections::HashMap::new(),
+        "".into()
+    ).unwrap();
+    sites::new_url_record(url.into(), title.into(), description.into())
+        .unwrap();
+}
+
+/// Initializes the tables of the test database, once. The database of the
+/// previous run is removed first, so that runs don't leave databases behind.
+/// WARN: Tests must call this function before using the database.
+#[cfg(test)]
+pub fn init_test_tables() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+
+    INIT.call_once(|| {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = crate::DB_PATH.clone().into_os_string();
+
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+        init_tables();
+    });
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use crate::{db::{add_missing_columns, terms}, DB_POOL};
+
+// Field flags are stored as a bitmask in the `fields` column of a posting, they
+// tell in which parts of a page a term has been found.
+// INFO: Postings migrated from the legacy `w_{word}` tables have no flags, and
+// their score is kept as the body frequency.
+pub const FIELD_TITLE: u8 = 1;
+pub const FIELD_DESCRIPTION: u8 = 1 << 1;
+pub const FIELD_HEADING: u8 = 1 << 2;
+pub const FIELD_BODY: u8 = 1 << 3;
+
+/// Maximum number of positions kept for a term on a site.
+pub const MAX_POSITIONS: usize = 512;
+
+/// Number of occurences of a term in each field of a site. Those are kept apart
+/// as every field has its own weight when ranking results.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FieldFrequencies {
+    pub title: usize,
+    pub description: usize,
+    pub heading: usize,
+    pub body: usize
+}
+
+impl FieldFrequencies {
+    /// Counts a new occurence of the term in a field.
+    pub fn incr(&mut self, field: u8) {
+        match field {
+            FIELD_TITLE => self.title += 1,
+            FIELD_DESCRIPTION => self.description += 1,
+            FIELD_HEADING => self.heading += 1,
+            _ => self.body += 1
+        }
+    }
+
+    /// Returns the field flags of the fields the term has been found in.
+    pub fn flags(&self) -> u8 {
+        [
+            (self.title, FIELD_TITLE),
+            (self.description, FIELD_DESCRIPTION),
+            (self.heading, FIELD_HEADING),
+            (self.body, FIELD_BODY)
+        ]
+            .iter()
+            .filter(|(tf, _)| *tf > 0)
+            .fold(0, |flags, (_, field)| flags | field)
+    }
+}
+
+const CREATE_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS postings (
+        term_id INTEGER NOT NULL,
+        site_id INTEGER NOT NULL,
+        fields INTEGER NOT NULL DEFAULT 0,
+        tf_title INTEGER NOT NULL DEFAULT 0,
+        tf_description INTEGER NOT NULL DEFAULT 0,
+        tf_heading INTEGER NOT NULL DEFAULT 0,
+        tf_body INTEGER NOT NULL DEFAULT 0,
+        positions BLOB NOT NULL DEFAULT x'',
+        PRIMARY KEY (term_id, site_id),
+        CONSTRAINT term_id FOREIGN KEY (term_id) REFERENCES terms(id),
+        CONSTRAINT site_id FOREIGN KEY (site_id) REFERENCES sites(id)
+    ) WITHOUT ROWID
+";
+
+/// Encodes word positions to be stored in the `positions` column.
+pub fn encode_positions(positions: &[u32]) -> Vec<u8> {
+    positions.iter().flat_map(|p| p.to_le_bytes()).collect()
+}
+
+/// Decodes word positions encoded by `encode_positions`.
+pub fn decode_positions(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Initalizes the table if it doesn't exists already. A posting links a term
+/// of the `terms` table to a site of the `sites` table with the frequencies
+/// of the term in each field of the site, and the positions of the term on
+/// the site.
+/// INFO: Postings saved before positions were recorded have none, those can't
+/// match phrase queries until their site is indexed again.
+/// INFO: Tables created when postings held a single pre-weighted score are
+/// rebuilt, this score is kept as the body frequency.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let columns = conn
+        .prepare("SELECT name FROM pragma_table_info('postings')")?
+        .query_map([], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    if columns.contains(&"score".to_string()) {
+        println!("[POSTINGS] Migrating table to field frequencies");
+        conn.execute_batch(&format!("
+            BEGIN IMMEDIATE;
+            {};
+            INSERT INTO postings_v2 (term_id, site_id, fields, tf_body)
+            SELECT term_id, site_id, fields, score FROM postings;
+            DROP TABLE postings;
+            ALTER TABLE postings_v2 RENAME TO postings;
+            COMMIT;
+        ", CREATE_TABLE.replace("EXISTS postings", "EXISTS postings_v2")))?;
+    }
+    conn.execute_batch(&format!("
+        {CREATE_TABLE};
+        CREATE INDEX IF NOT EXISTS postings_by_term ON postings (term_id);
+        CREATE INDEX IF NOT EXISTS postings_by_site ON postings (site_id);
+    "))?;
+    add_missing_columns(
+        &conn, "postings", &[("positions", "BLOB NOT NULL DEFAULT x''")]
+    )?;
+    Ok(())
+}
+
+/// Saves the field frequencies and positions of every word of a site in a
+/// single transaction, and updates the document frequency of those words.
+/// WARN: The URL should exist in the `sites` table and have no postings. Call
+/// `db::sites::new_url_record` to make sure it's the case.
+pub fn save_site_postings(
+    url: &String,
+    words: &HashMap<String, (FieldFrequencies, Vec<u32>)>
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let tx = conn.transaction()?;
+    let site_id: i64 = tx.query_row(
+        "SELECT id FROM sites WHERE url = ?1", params![url], |row| row.get(0)
+    )?;
+
+    {
+        let mut insert = tx.prepare_cached("
+            INSERT OR REPLACE INTO postings (
+                term_id,
+                site_id,
+                fields,
+                tf_title,
+                tf_description,
+                tf_heading,
+                tf_body,
+                positions
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ")?;
+
+        for (word, (tf, positions)) in words {
+            let term_id = terms::get_or_create_id(&tx, word)?;
+
+            insert.execute(params![
+                term_id,
+                site_id,
+                tf.flags(),
+                tf.title as i64,
+                tf.description as i64,
+                tf.heading as i64,
+                tf.body as i64,
+                encode_positions(positions)
+            ])?;
+        }
+    }
+    terms::update_site_terms_df(&tx, site_id, 1)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Moves the content of the legacy per-word `w_{word}` tables to the postings
+/// store, each table is dropped once its content has been moved.
+/// INFO: Rows referring to URLs missing from `sites` are lost in the process.
+pub fn migrate_word_tables() -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let tables = conn
+        .prepare("
+            SELECT name FROM sqlite_master
+            WHERE type = 'table' AND name LIKE 'w\\_%' ESCAPE '\\'
+        ")?
+        .query_map([], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    if tables.is_empty() {
+        return Ok(());
+    }
+    println!("[POSTINGS] Migrating {} word tables", tables.len());
+    for table in tables {
+        let word = table.strip_prefix("w_").unwrap_or(&table);
+        let quoted = format!("\"{}\"", table.replace("\"", "\"\""));
+        let tx = conn.transaction()?;
+        let term_id = terms::get_or_create_id(&tx, word)?;
+
+        tx.execute(&format!("
+            INSERT INTO postings (term_id, site_id, tf_body)
+            SELECT ?1, sites.id, SUM(legacy.score)
+            FROM {quoted} AS legacy
+            INNER JOIN sites ON sites.url = legacy.url
+            WHERE true
+            GROUP BY sites.id
+            ON CONFLICT (term_id, site_id) DO UPDATE SET
+                tf_body = tf_body + excluded.tf_body
+        "), params![term_id])?;
+        tx.execute("
+            UPDATE terms
+            SET df = (SELECT COUNT(1) FROM postings WHERE term_id = ?1)
+            WHERE id = ?1
+        ", params![term_id])?;
+        tx.execute(&format!("DROP TABLE {quoted}"), [])?;
+        tx.commit()?;
+    }
+    Ok(())
+}
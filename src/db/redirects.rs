@@ -0,0 +1,74 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use rusqlite::OptionalExtension;
+use crate::indexer::fetch::{Redirect, MAX_REDIRECTS};
+use crate::DB_POOL;
+
+/// Initializes the table if it doesn't exists already. This table holds the
+/// redirects followed by the crawler, from a URL to the URL it redirects to.
+/// INFO: Only the last redirect of a URL is kept, URLs that were permanently
+/// redirected are rewritten before being queued, see `resolve`.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS redirects (
+            url TEXT PRIMARY KEY,
+            target TEXT NOT NULL,
+            status INTEGER NOT NULL,
+            redirected_at INTEGER NOT NULL
+        )
+    ", [])?;
+    Ok(())
+}
+
+/// Records the redirects followed to fetch a page.
+pub fn record(
+    redirects: &[Redirect]
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let tx = conn.transaction()?;
+
+    for redirect in redirects {
+        tx.execute("
+            INSERT OR REPLACE INTO redirects
+                (url, target, status, redirected_at)
+            VALUES (?1, ?2, ?3, ?4)
+        ", params![redirect.from, redirect.to, redirect.status, now as i64])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Forgets the redirect of a URL, such as a URL that was fetched without being
+/// redirected.
+pub fn forget(url: &String) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("DELETE FROM redirects WHERE url = ?1", params![url])?;
+    Ok(())
+}
+
+/// Returns the URL a URL permanently redirects to, following the chain of
+/// permanent redirects. URLs that aren't permanently redirected are returned
+/// as-is.
+pub fn resolve(url: String) -> Result<String, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let mut select = conn.prepare("
+        SELECT target FROM redirects WHERE url = ?1 AND status IN (301, 308)
+    ")?;
+    let mut url = url;
+
+    // INFO: Redirect loops are forgotten when fetched, but the chain length
+    // is still bounded in case the table holds one.
+    for _ in 0..MAX_REDIRECTS {
+        match select
+            .query_row(params![url], |row| row.get::<usize, String>(0))
+            .optional()?
+        {
+            Some(target) => url = target,
+            None => break
+        }
+    }
+    Ok(url)
+}
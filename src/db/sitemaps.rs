@@ -0,0 +1,203 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use rusqlite::{OptionalExtension, Row};
+use serde_derive::{Deserialize, Serialize};
+use url::Url;
+use crate::{db::escape_like, DB_POOL};
+
+/// A sitemap to read, as saved on the database. Sitemaps listed by a sitemap
+/// index have it as their parent.
+/// INFO: The status is `pending` until the sitemap is read, then `ok` or
+/// `error`, the error being kept until the sitemap is read successfully.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SitemapRecord {
+    pub url: String,
+    pub domain: String,
+    pub parent: Option<String>,
+    pub kind: Option<String>,
+    pub status: String,
+    pub url_count: i64,
+    pub error: Option<String>,
+    pub last_fetched_at: Option<DateTime<Utc>>,
+    pub next_fetch_at: DateTime<Utc>
+}
+
+impl SitemapRecord {
+    fn from_row(row: &Row) -> Result<Self, rusqlite::Error> {
+        let date = |ms: i64| DateTime::from_timestamp_millis(ms)
+            .unwrap_or_default();
+
+        Ok(Self {
+            url: row.get(0)?,
+            domain: row.get(1)?,
+            parent: row.get(2)?,
+            kind: row.get(3)?,
+            status: row.get(4)?,
+            url_count: row.get(5)?,
+            error: row.get(6)?,
+            last_fetched_at: row.get::<usize, Option<i64>>(7)?.map(date),
+            next_fetch_at: date(row.get(8)?)
+        })
+    }
+}
+
+const SELECT_RECORD: &str = "
+    SELECT url, domain, parent, kind, status, url_count, error,
+        last_fetched_at, next_fetch_at
+    FROM sitemaps
+";
+
+/// Initializes the table if it doesn't exists already. This table holds the
+/// sitemaps to read, with the result of their last fetch and when they must be
+/// read again. Times are in milliseconds since the UNIX epoch.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS sitemaps (
+            url TEXT PRIMARY KEY,
+            domain TEXT NOT NULL,
+            parent TEXT,
+            kind TEXT,
+            status TEXT NOT NULL DEFAULT 'pending',
+            url_count INTEGER NOT NULL DEFAULT 0,
+            error TEXT,
+            last_fetched_at INTEGER,
+            next_fetch_at INTEGER NOT NULL
+        )
+    ", [])?;
+    conn.execute("
+        CREATE INDEX IF NOT EXISTS sitemaps_next_fetch_at
+        ON sitemaps (next_fetch_at)
+    ", [])?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS sitemaps_domain ON sitemaps (domain)",
+        []
+    )?;
+    Ok(())
+}
+
+/// Saves a sitemap to read, a new sitemap is read as soon as possible. A known
+/// sitemap keeps its schedule, unless it was modified after its last fetch
+/// according to the `lastmod` of its sitemap index.
+/// Returns true if the sitemap wasn't known yet.
+pub fn save_sitemap(
+    url: &str,
+    parent: Option<&str>,
+    lastmod: Option<DateTime<Utc>>
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let domain = Url::parse(url)?.host_str().unwrap_or("").to_string();
+    let now = Utc::now().timestamp_millis();
+    let inserted = conn.execute("
+        INSERT OR IGNORE INTO sitemaps (url, domain, parent, next_fetch_at)
+        VALUES (?1, ?2, ?3, ?4)
+    ", params![url, domain, parent, now])?;
+
+    if inserted == 0 {
+        if let Some(lastmod) = lastmod {
+            conn.execute("
+                UPDATE sitemaps SET next_fetch_at = MIN(next_fetch_at, ?1)
+                WHERE url = ?2 AND last_fetched_at < ?3
+            ", params![now, url, lastmod.timestamp_millis()])?;
+        }
+    }
+    Ok(inserted > 0)
+}
+
+/// Returns the sitemap that must be read first, `None` if there is none.
+pub fn get_next_sitemap(
+) -> Result<Option<SitemapRecord>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let sitemap = conn
+        .query_row(
+            &format!("{SELECT_RECORD} ORDER BY next_fetch_at LIMIT 1"),
+            [],
+            SitemapRecord::from_row
+        )
+        .optional()?;
+
+    Ok(sitemap)
+}
+
+/// Saves the result of the fetch of a sitemap, and schedules its next fetch.
+/// An error is given if the sitemap couldn't be read, its kind and number of
+/// URLs are then kept from its last successful fetch.
+pub fn update_fetch(
+    url: &str,
+    result: Result<(Option<&str>, usize), String>,
+    next_fetch_in: Duration
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let now = Utc::now().timestamp_millis();
+    let next_fetch_at = now + next_fetch_in.as_millis() as i64;
+
+    match result {
+        Ok((kind, url_count)) => conn.execute("
+            UPDATE sitemaps
+            SET kind = ?1, status = 'ok', url_count = ?2, error = NULL,
+                last_fetched_at = ?3, next_fetch_at = ?4
+            WHERE url = ?5
+        ", params![kind, url_count as i64, now, next_fetch_at, url])?,
+        Err(error) => conn.execute("
+            UPDATE sitemaps
+            SET status = 'error', error = ?1, last_fetched_at = ?2,
+                next_fetch_at = ?3
+            WHERE url = ?4
+        ", params![error, now, next_fetch_at, url])?
+    };
+    Ok(())
+}
+
+/// Returns the sitemaps of a domain and of its subdomains.
+pub fn get_sitemaps_of_domain(
+    domain: &str
+) -> Result<Vec<SitemapRecord>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let mut select = conn.prepare(&format!("
+        {SELECT_RECORD}
+        WHERE domain = ?1 OR domain LIKE '%.' || ?2 ESCAPE '\\'
+        ORDER BY domain, url
+    "))?;
+    let sitemaps = select
+        .query_map(
+            params![domain, escape_like(domain)],
+            SitemapRecord::from_row
+        )?
+        .collect::<Result<Vec<SitemapRecord>, _>>()?;
+
+    Ok(sitemaps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_are_matched_literally() {
+        crate::db::init_test_tables();
+
+        for url in [
+            "https://sitemaps.test/sitemap.xml",
+            "https://blog.sitemaps.test/sitemap.xml",
+            "https://sitemapsxtest/sitemap.xml",
+            "https://other.test/sitemap.xml"
+        ] {
+            save_sitemap(url, None, None).unwrap();
+        }
+
+        let urls = |domain: &str| get_sitemaps_of_domain(domain).unwrap()
+            .into_iter()
+            .map(|s| s.url)
+            .collect::<Vec<String>>();
+
+        assert_eq!(urls("sitemaps.test"), vec![
+            "https://blog.sitemaps.test/sitemap.xml",
+            "https://sitemaps.test/sitemap.xml"
+        ]);
+        assert!(urls("%").is_empty());
+        assert!(urls("_itemaps.test").is_empty());
+        assert!(urls("sitemaps_test").is_empty());
+        assert!(urls("\\").is_empty());
+    }
+}
@@ -1,8 +1,11 @@
 use serde_derive::{Deserialize, Serialize};
 use url::Url;
+use rusqlite::{Connection, OptionalExtension};
+use crate::analysis::ANALYSIS_VERSION;
+use crate::db::{add_missing_columns, corpus, escape_like, terms};
+use crate::indexer::fetch::Validators;
 use crate::indexer::localization::Localization;
 use crate::DB_POOL;
-use crate::sanitize::sql_escape_ap;
 
 /// Refers to an indexed page
 /// NOTE: When moving from SQL to Diesel + Pg, please, PLEASE refer to pages
@@ -17,19 +20,68 @@ pub struct SiteRecord {
     pub loc: String
 }
 
-/// Initialize this table if it does not exists on the database.
-pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
-    let conn = DB_POOL.clone().get().unwrap();
-
-    conn.execute("CREATE TABLE IF NOT EXISTS sites (
-        url TEXT PRIMARY KEY,
+const CREATE_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS sites (
+        id INTEGER PRIMARY KEY,
+        url TEXT UNIQUE NOT NULL,
         domain TEXT,
         title TEXT,
         description TEXT,
         ttr REAL,
         loc TEXT,
+        length INTEGER NOT NULL DEFAULT 0,
+        content TEXT NOT NULL DEFAULT '',
+        cluster TEXT,
+        etag TEXT,
+        last_modified TEXT,
+        content_hash TEXT,
+        analysis_version TEXT,
         CONSTRAINT domain FOREIGN KEY (domain) REFERENCES domains(domain)
-    )", [])?;
+    )
+";
+
+/// Initialize this table if it does not exists on the database.
+/// INFO: Tables created before sites had an integer id (used by the postings
+/// store) are rebuilt with their content. The `length` column is the number
+/// of words found on the site, and the `content` column its body text used to
+/// show snippets on search results. The `cluster` column is shared by the
+/// versions of a page in different languages, see `db::alternates`. The
+/// `etag`, `last_modified` and `content_hash` columns are used to re-crawl
+/// the site conditionally, see `Validators`, as long as its `analysis_version`
+/// is the current `ANALYSIS_VERSION`.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let columns = conn.prepare("SELECT name FROM pragma_table_info('sites')")?
+        .query_map([], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    if !columns.is_empty() && !columns.contains(&"id".to_string()) {
+        println!("[SITES] Migrating table to integer ids");
+        // Legacy word tables still refer to this table, hence foreign keys
+        // are not enforced while it's rebuilt. They're enforced again after,
+        // as on every connection of the pool.
+        conn.execute_batch(&format!("
+            PRAGMA foreign_keys = OFF;
+            BEGIN IMMEDIATE;
+            {};
+            INSERT INTO sites_v2 (url, domain, title, description, ttr, loc)
+            SELECT url, domain, title, description, ttr, loc FROM sites;
+            DROP TABLE sites;
+            ALTER TABLE sites_v2 RENAME TO sites;
+            COMMIT;
+            PRAGMA foreign_keys = ON;
+        ", CREATE_TABLE.replace("EXISTS sites", "EXISTS sites_v2")))?;
+    }
+    conn.execute(CREATE_TABLE, [])?;
+    add_missing_columns(&conn, "sites", &[
+        ("length", "INTEGER NOT NULL DEFAULT 0"),
+        ("content", "TEXT NOT NULL DEFAULT ''"),
+        ("cluster", "TEXT"),
+        ("etag", "TEXT"),
+        ("last_modified", "TEXT"),
+        ("content_hash", "TEXT"),
+        ("analysis_version", "TEXT")
+    ])?;
     Ok(())
 }
 
@@ -44,7 +96,7 @@ pub fn get_rows_number() -> isize {
         
         return row_0.unwrap().get::<usize, isize>(0).unwrap();
     }
-    return -1;
+    -1
 }
 
 /// Create a new record of an indexed URL, multiple URLs of the same websites 
@@ -56,18 +108,134 @@ pub fn new_url_record(
     title: String, 
     description: String
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let conn = DB_POOL.clone().get().unwrap();
+    let mut conn = DB_POOL.clone().get().unwrap();
     let url_obj = Url::parse(&url)?;
-    let domain = sql_escape_ap(url_obj.domain().unwrap().to_string());
-    let url = sql_escape_ap(url);
-    let title = sql_escape_ap(title);
-    let description = sql_escape_ap(description);
+    let domain = url_obj.domain().unwrap().to_string();
+    let tx = conn.transaction()?;
 
-    conn.execute(&format!("DELETE FROM sites WHERE url = '{url}'"), [])?;
-    conn.execute(&format!("
+    delete_record(&tx, &url)?;
+    tx.execute("
             INSERT INTO sites (url, domain, title, description, ttr, loc) 
-            VALUES ('{url}', '{domain}', '{title}', '{description}', 0.0, 'en')
-    "), [])?;
+            VALUES (?1, ?2, ?3, ?4, 0.0, 'en')
+    ", params![url, domain, title, description])?;
+    corpus::update(&tx, 1, 0)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Deletes the record of a URL and the postings linked to it, if any. Returns
+/// true if a record was deleted.
+/// INFO: A connection is taken to allow this function to be used inside of
+/// a transaction.
+fn delete_record(
+    conn: &Connection,
+    url: &String
+) -> Result<bool, rusqlite::Error> {
+    let previous = conn
+        .query_row(
+            "SELECT id, length FROM sites WHERE url = ?1",
+            params![url],
+            |row| Ok((row.get::<usize, i64>(0)?, row.get::<usize, i64>(1)?))
+        )
+        .optional()?;
+
+    // Statistics used for ranking have to forget about the previous record.
+    if let Some((site_id, length)) = previous {
+        terms::update_site_terms_df(conn, site_id, -1)?;
+        conn.execute(
+            "DELETE FROM postings WHERE site_id = ?1", params![site_id]
+        )?;
+        conn.execute("DELETE FROM sites WHERE id = ?1", params![site_id])?;
+        corpus::update(conn, -1, -length)?;
+    }
+    Ok(previous.is_some())
+}
+
+/// Deletes the record of an indexed URL and all the data linked to it, such
+/// as a page that must not be indexed anymore. Returns true if the URL was
+/// indexed.
+pub fn delete_url_record(
+    url: &String
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let tx = conn.transaction()?;
+    let deleted = delete_record(&tx, url)?;
+
+    tx.commit()?;
+    Ok(deleted)
+}
+
+/// Returns true if a URL is indexed.
+pub fn is_url_indexed(url: &String) -> Result<bool, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    let mut select = conn.prepare("SELECT 1 FROM sites WHERE url = ?1")?;
+    let indexed = select.exists(params![url])?;
+
+    Ok(indexed)
+}
+
+/// Returns the validators of an indexed URL, `None` if it isn't indexed or if
+/// it was indexed with another `ANALYSIS_VERSION`, it must then be indexed
+/// again whether it changed or not.
+pub fn get_validators(
+    url: &String
+) -> Result<Option<Validators>, Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let validators = conn
+        .query_row("
+            SELECT etag, last_modified, content_hash FROM sites
+            WHERE url = ?1 AND analysis_version = ?2
+        ", params![url, *ANALYSIS_VERSION],
+            |row| Ok(Validators {
+                etag: row.get(0)?,
+                last_modified: row.get(1)?,
+                content_hash: row.get(2)?
+            })
+        )
+        .optional()?;
+
+    Ok(validators)
+}
+
+/// Updates the validators of a site, received with its last fetch, and the
+/// `ANALYSIS_VERSION` it's indexed with.
+pub fn update_site_validators(
+    url: &String, validators: &Validators
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        UPDATE sites
+        SET etag = ?1, last_modified = ?2, content_hash = ?3,
+            analysis_version = ?4
+        WHERE url = ?5
+    ", params![
+        validators.etag,
+        validators.last_modified,
+        validators.content_hash,
+        *ANALYSIS_VERSION,
+        url
+    ])?;
+    Ok(())
+}
+
+/// Updates the number of words found on a site.
+pub fn update_site_length(
+    url: &String, length: usize
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = DB_POOL.clone().get().unwrap();
+    let tx = conn.transaction()?;
+    let previous: i64 = tx.query_row(
+        "SELECT length FROM sites WHERE url = ?1", params![url], |row| row.get(0)
+    )?;
+
+    tx.execute(
+        "UPDATE sites SET length = ?1 WHERE url = ?2",
+        params![length as i64, url]
+    )?;
+    corpus::update(&tx, 0, length as i64 - previous)?;
+    tx.commit()?;
     Ok(())
 }
 
@@ -76,10 +244,21 @@ pub fn update_site_ttr(
     url: &String, ttr: f64
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = DB_POOL.clone().get().unwrap();
-    let url = sql_escape_ap(url.into());
 
     conn.execute(
-        &format!("UPDATE sites SET ttr = {ttr} WHERE url = '{url}'"), []
+        "UPDATE sites SET ttr = ?1 WHERE url = ?2", params![ttr, url]
+    )?;
+    Ok(())
+}
+
+/// Updates the body text of a site.
+pub fn update_site_content(
+    url: &String, content: &str
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute(
+        "UPDATE sites SET content = ?1 WHERE url = ?2", params![content, url]
     )?;
     Ok(())
 }
@@ -89,10 +268,9 @@ pub fn update_site_loc(
     url: &String, loc: Localization
 ) -> Result<(), Box<dyn std::error::Error>> {
     let conn = DB_POOL.clone().get().unwrap();
-    let url = sql_escape_ap(url.into());
 
     conn.execute(
-        &format!("UPDATE sites SET loc = '{}' WHERE url = '{url}'", loc.0), []
+        "UPDATE sites SET loc = ?1 WHERE url = ?2", params![loc.0, url]
     )?;
     Ok(())
 }
@@ -102,13 +280,14 @@ pub fn get_all_sites_records_of_a_domain(
 ) -> Result<Vec<SiteRecord>, Box<dyn std::error::Error>> {
     let conn = DB_POOL.clone().get().unwrap();
     
-    let mut select = conn.prepare(&format!("
-        SELECT *
+    let mut select = conn.prepare("
+        SELECT url, domain, title, description, ttr, loc
         FROM sites
-        WHERE domain LIKE '%{domain}'
-    ")).unwrap();
+        WHERE domain = ?1 OR domain LIKE '%.' || ?2 ESCAPE '\\'
+    ").unwrap();
 
-    let results = select.query_map([], |row| Ok((
+    let pattern = escape_like(&domain);
+    let results = select.query_map(params![domain, pattern], |row| Ok((
         row.get::<usize, String>(0).unwrap(),
         row.get::<usize, String>(1).unwrap(),
         row.get::<usize, String>(2).unwrap(),
@@ -133,3 +312,88 @@ pub fn get_all_sites_records_of_a_domain(
     });
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_test_tables, new_test_site as new_site};
+
+    fn urls_of_domain(domain: &str) -> Vec<String> {
+        let mut urls = get_all_sites_records_of_a_domain(domain.to_string())
+            .unwrap()
+            .into_iter()
+            .map(|r| r.url)
+            .collect::<Vec<String>>();
+
+        urls.sort();
+        urls
+    }
+
+    #[test]
+    fn hostile_titles_and_urls_are_stored_as_is() {
+        init_test_tables();
+        let url = "http://hostile.test/it's%27--;".to_string();
+        let title = "'); DROP TABLE sites; --".to_string();
+        let description = "\" OR 1=1 -- 100% _done_".to_string();
+
+        new_site(&url, &title, &description);
+        update_site_loc(&url, ("fr' OR '1'='1".into(), 1.)).unwrap();
+
+        let records = get_all_sites_records_of_a_domain("hostile.test".into())
+            .unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].url, url);
+        assert_eq!(records[0].title, title);
+        assert_eq!(records[0].description, description);
+        assert_eq!(records[0].loc, "fr' OR '1'='1");
+        assert!(is_url_indexed(&url).unwrap());
+    }
+
+    #[test]
+    fn domains_are_matched_literally() {
+        init_test_tables();
+        for url in [
+            "http://like-test.example/",
+            "http://www.like-test.example/a",
+            "http://notlike-test.example/",
+            "http://like-test.example.org/"
+        ] {
+            new_site(url, "", "");
+        }
+
+        assert_eq!(urls_of_domain("like-test.example"), vec![
+            "http://like-test.example/",
+            "http://www.like-test.example/a"
+        ]);
+        assert_eq!(urls_of_domain("www.like-test.example"), vec![
+            "http://www.like-test.example/a"
+        ]);
+        assert!(urls_of_domain("%").is_empty());
+        assert!(urls_of_domain("%like-test.example").is_empty());
+        assert!(urls_of_domain("like_test.example").is_empty());
+        assert!(urls_of_domain("like-test.example' OR '1'='1").is_empty());
+        assert!(urls_of_domain("like-test.%").is_empty());
+    }
+
+    #[test]
+    fn validators_expire_with_the_analysis_version() {
+        init_test_tables();
+        let url = "http://validators.test/".to_string();
+        let validators = Validators {
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+            content_hash: Some("hash".into())
+        };
+
+        new_site(&url, "", "");
+        update_site_validators(&url, &validators).unwrap();
+        assert_eq!(get_validators(&url).unwrap(), Some(validators));
+
+        DB_POOL.clone().get().unwrap().execute(
+            "UPDATE sites SET analysis_version = '0-old' WHERE url = ?1",
+            params![url]
+        ).unwrap();
+        assert_eq!(get_validators(&url).unwrap(), None);
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::DB_POOL;
+
+/// Initalizes the table if it doesn't exists already. This table holds the
+/// URLs the crawler didn't fetch, with the reason they were skipped, such as
+/// being disallowed by the robots file of their domain.
+/// INFO: Only the last time a URL was skipped is kept.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS skipped_urls (
+            url TEXT PRIMARY KEY,
+            reason TEXT NOT NULL,
+            skipped_at INTEGER NOT NULL
+        )
+    ", [])?;
+    Ok(())
+}
+
+/// Records that a URL was skipped by the crawler.
+pub fn record(url: &str, reason: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+    conn.execute("
+        INSERT OR REPLACE INTO skipped_urls (url, reason, skipped_at)
+        VALUES (?1, ?2, ?3)
+    ", params![url, reason, now as i64])?;
+    Ok(())
+}
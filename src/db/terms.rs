@@ -0,0 +1,57 @@
+use rusqlite::Connection;
+use crate::{db::add_missing_columns, DB_POOL};
+
+/// Initalizes the term dictionary if it doesn't exists already. Every word
+/// found while indexing gets a single row here, and is referred to by its id
+/// in the `postings` table. The `df` column is the number of sites the term
+/// has been found on.
+pub fn init_table() -> Result<(), Box<dyn std::error::Error>> {
+    let conn = DB_POOL.clone().get().unwrap();
+
+    conn.execute("
+        CREATE TABLE IF NOT EXISTS terms (
+            id INTEGER PRIMARY KEY,
+            term TEXT UNIQUE NOT NULL,
+            df INTEGER NOT NULL DEFAULT 0
+        )
+    ", [])?;
+    let added = add_missing_columns(
+        &conn, "terms", &[("df", "INTEGER NOT NULL DEFAULT 0")]
+    )?;
+
+    if !added.is_empty() {
+        conn.execute("
+            UPDATE terms
+            SET df = (SELECT COUNT(1) FROM postings WHERE term_id = terms.id)
+        ", [])?;
+    }
+    Ok(())
+}
+
+/// Returns the id of a term, creating it if it's not in the dictionary yet.
+/// INFO: A connection is taken to allow this function to be used inside of
+/// a transaction.
+pub fn get_or_create_id(
+    conn: &Connection,
+    term: &str
+) -> Result<i64, rusqlite::Error> {
+    conn.prepare_cached("INSERT OR IGNORE INTO terms (term) VALUES (?1)")?
+        .execute(params![term])?;
+    conn.prepare_cached("SELECT id FROM terms WHERE term = ?1")?
+        .query_row(params![term], |row| row.get(0))
+}
+
+/// Changes the document frequency of every term found on a site.
+/// INFO: A connection is taken to allow this function to be used inside of
+/// a transaction.
+pub fn update_site_terms_df(
+    conn: &Connection,
+    site_id: i64,
+    delta: i64
+) -> Result<(), rusqlite::Error> {
+    conn.execute("
+        UPDATE terms SET df = df + ?1
+        WHERE id IN (SELECT term_id FROM postings WHERE site_id = ?2)
+    ", params![delta, site_id])?;
+    Ok(())
+}
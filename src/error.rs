@@ -16,6 +16,7 @@ impl StdError {
         Box::new(Self(self.0.clone()))
     }
 
+    #[allow(dead_code)]
     pub fn to_boxed_err(&self) -> Result<(), Box<dyn std::error::Error>> {
         Err(self.to_boxed())
     } 
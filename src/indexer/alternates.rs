@@ -0,0 +1,105 @@
+use scraper::{Html, Selector};
+use url::Url;
+use super::canonical::{has_rel, parse_link_header, resolve_url};
+
+/// Maximum number of alternates kept for a page, a page can't reasonably be
+/// translated in more languages.
+const MAX_ALTERNATES: usize = 64;
+
+/// A version of a page in another language, declared by the page with an
+/// `hreflang` link. The `hreflang` is lowercased, it's a language tag or
+/// `x-default` for the version shown to users of other languages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alternate {
+    pub hreflang: String,
+    pub url: String
+}
+
+/// Returns true if an `hreflang` value looks like `fr`, `fr-ca` or `x-default`.
+fn is_hreflang(hreflang: &str) -> bool {
+    let primary = hreflang.split('-').next().unwrap_or("");
+
+    hreflang == "x-default" || (
+        (2..=3).contains(&primary.len()) &&
+        primary.chars().all(|c| c.is_ascii_alphabetic()) &&
+        hreflang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    )
+}
+
+/// Returns the alternates of a `Link` header.
+fn header_alternates(header: &str, base: &Url) -> Vec<Alternate> {
+    parse_link_header(header)
+        .into_iter()
+        .filter(|(_, params)| params.iter().any(|(name, value)| {
+            name == "rel" && has_rel(value, "alternate")
+        }))
+        .filter_map(|(href, params)| Some(Alternate {
+            hreflang: params.into_iter()
+                .find(|(name, _)| name == "hreflang")?
+                .1
+                .to_lowercase(),
+            url: resolve_url(base, &href)?
+        }))
+        .collect()
+}
+
+/// Returns the alternates declared by a page with
+/// `link[rel="alternate"][hreflang]` tags and by its server with the `Link`
+/// header. A page usually declares itself among its alternates.
+/// INFO: URLs are normalized and relative ones are resolved against the URL of
+/// the page, invalid or duplicated alternates are left out.
+pub fn get_alternates(
+    page: &Html,
+    header: Option<&str>,
+    url: &Url
+) -> Vec<Alternate> {
+    let link_selector = Selector::parse("link[hreflang][href]").unwrap();
+    let links = page.select(&link_selector)
+        .filter(|e| e.attr("rel").is_some_and(|rel| has_rel(rel, "alternate")))
+        .filter_map(|e| Some(Alternate {
+            hreflang: e.attr("hreflang")?.trim().to_lowercase(),
+            url: resolve_url(url, e.attr("href")?)?
+        }));
+    let mut alternates: Vec<Alternate> = vec![];
+
+    for alternate in links.chain(
+        header.map(|h| header_alternates(h, url)).unwrap_or_default()
+    ) {
+        let alternate = Alternate {
+            hreflang: alternate.hreflang.replace('_', "-"),
+            ..alternate
+        };
+
+        if alternates.len() >= MAX_ALTERNATES {
+            break;
+        }
+        if is_hreflang(&alternate.hreflang) &&
+            !alternates.contains(&alternate)
+        {
+            alternates.push(alternate);
+        }
+    }
+    alternates
+}
+
+/// Returns the language a page declares for itself among its alternates.
+pub fn own_hreflang<'a>(
+    url: &str,
+    alternates: &'a [Alternate]
+) -> Option<&'a str> {
+    alternates.iter()
+        .find(|a| a.url == url && a.hreflang != "x-default")
+        .map(|a| a.hreflang.as_str())
+}
+
+/// Returns the key of the cluster of a page and its alternates, which is the
+/// smallest of their URLs. As each version of a page should declare the same
+/// alternates, every version gets the same key.
+/// Returns `None` if the page has no alternates.
+pub fn cluster_key(url: &str, alternates: &[Alternate]) -> Option<String> {
+    alternates.iter()
+        .map(|a| a.url.as_str())
+        .filter(|u| *u != url)
+        .min()
+        .map(|u| u.min(url).to_string())
+}
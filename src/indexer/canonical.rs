@@ -0,0 +1,260 @@
+use std::env;
+use scraper::{Html, Selector};
+use url::Url;
+
+lazy_static! {
+    /// Query parameters removed from URLs as they only track visitors, a
+    /// trailing `*` matches any parameter starting with the pattern.
+    static ref TRACKING_PARAMS: Vec<String> = env::var("TRACKING_PARAMS")
+        .unwrap_or(DEFAULT_TRACKING_PARAMS.into())
+        .split(',')
+        .map(|p| p.trim().to_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect();
+}
+
+const DEFAULT_TRACKING_PARAMS: &str = "utm_*,gclid,dclid,fbclid,msclkid,\
+    yclid,igshid,mc_cid,mc_eid,_ga,_gl";
+
+/// Returns true if a query parameter only tracks visitors.
+fn is_tracking_param(name: &str) -> bool {
+    let name = name.to_lowercase();
+
+    TRACKING_PARAMS.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == *pattern
+    })
+}
+
+/// Normalizes a URL for the same page to always have the same URL:
+/// - The fragment is removed.
+/// - Tracking parameters are removed from the query, see `TRACKING_PARAMS`.
+/// - Parameters of the query are sorted, an empty query is removed.
+/// - Trailing slashes are removed from paths, except from the root path, as
+///   `/docs/` and `/docs` are almost always the same page.
+///
+/// INFO: The URL parser already lowercases hosts, removes default ports and
+/// resolves `.` and `..` segments of paths. Parameters keep their encoding.
+/// WARN: The scheme is kept, `http` URLs aren't turned into `https` ones as it
+/// can't be known without a request whether a site serves both. Sites serving
+/// both almost always redirect one to the other or declare a canonical URL,
+/// the page is then only indexed under the URL they chose.
+pub fn normalize_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    let path = url.path().trim_end_matches('/').to_string();
+
+    if !path.is_empty() && path.len() < url.path().len() {
+        url.set_path(&path);
+    }
+    let mut params = url.query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter(|p| !is_tracking_param(p.split('=').next().unwrap()))
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>();
+
+    // Parameters of the same name keep their order, it may matter.
+    params.sort_by(|a, b| {
+        a.split('=').next().unwrap().cmp(b.split('=').next().unwrap())
+    });
+    url.set_fragment(None);
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.set_query(Some(&params.join("&")));
+    }
+    url
+}
+
+/// Returns the normalized URL of a link of a page, relative URLs are resolved
+/// against the URL of the page. Only HTTP URLs are kept.
+pub fn resolve_url(base: &Url, href: &str) -> Option<String> {
+    let url = base.join(href.trim()).ok()?;
+
+    if url.scheme() == "http" || url.scheme() == "https" {
+        Some(normalize_url(&url).to_string())
+    } else {
+        None
+    }
+}
+
+/// Returns the normalized form of a URL, `None` if it isn't an HTTP URL.
+pub fn normalize_str(url: &str) -> Option<String> {
+    let url = Url::parse(url.trim()).ok()?;
+
+    resolve_url(&url, "")
+}
+
+/// Returns true if a `rel` attribute or parameter holds a relation.
+pub fn has_rel(rel: &str, relation: &str) -> bool {
+    rel.split_whitespace().any(|r| r.eq_ignore_ascii_case(relation))
+}
+
+/// Splits a `Link` header into its links, commas inside of URLs are kept.
+fn split_link_header(header: &str) -> Vec<&str> {
+    let mut links = vec![];
+    let mut in_url = false;
+    let mut start = 0;
+
+    for (i, c) in header.char_indices() {
+        match c {
+            '<' => in_url = true,
+            '>' => in_url = false,
+            ',' if !in_url => {
+                links.push(&header[start..i]);
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    links.push(&header[start..]);
+    links
+}
+
+/// A link of a `Link` header, its URL and its parameters.
+pub type HeaderLink = (String, Vec<(String, String)>);
+
+/// Parses a `Link` header, such as
+/// `<https://example.com/fr/>; rel="alternate"; hreflang="fr"`. Each link is
+/// returned with its parameters, their names are lowercased.
+pub fn parse_link_header(header: &str) -> Vec<HeaderLink> {
+    split_link_header(header)
+        .into_iter()
+        .filter_map(|link| {
+            let mut parts = link.split(';');
+            let href = parts.next()?.trim()
+                .strip_prefix('<')?
+                .strip_suffix('>')?;
+            let params = parts
+                .filter_map(|part| part.split_once('='))
+                .map(|(name, value)| (
+                    name.trim().to_lowercase(),
+                    value.trim().trim_matches('"').to_string()
+                ))
+                .collect();
+
+            Some((href.to_string(), params))
+        })
+        .collect()
+}
+
+/// Returns the canonical URL of a page, declared with a
+/// `link[rel="canonical"]` tag or by its server with the `Link` header. The
+/// URL is normalized, and relative URLs are resolved against the URL of the
+/// page.
+pub fn get_canonical(
+    page: &Html,
+    header: Option<&str>,
+    url: &Url
+) -> Option<String> {
+    let link_selector = Selector::parse("link[rel][href]").unwrap();
+    let tag = page.select(&link_selector)
+        .find(|e| has_rel(e.attr("rel").unwrap(), "canonical"))
+        .map(|e| e.attr("href").unwrap().to_string());
+    let header = header.map(parse_link_header).unwrap_or_default()
+        .into_iter()
+        .find(|(_, params)| {
+            params.iter().any(|(name, value)| {
+                name == "rel" && has_rel(value, "canonical")
+            })
+        })
+        .map(|(href, _)| href);
+
+    tag.or(header).and_then(|href| resolve_url(url, &href))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(url: &str) -> String {
+        normalize_url(&Url::parse(url).unwrap()).to_string()
+    }
+
+    #[test]
+    fn fragments_are_removed() {
+        assert_eq!(
+            normalize("https://example.com/page#section"),
+            "https://example.com/page"
+        );
+        assert_eq!(
+            normalize("https://example.com/?a=1#"),
+            "https://example.com/?a=1"
+        );
+    }
+
+    #[test]
+    fn tracking_params_are_removed() {
+        assert_eq!(
+            normalize("https://example.com/?utm_source=x&id=3&UTM_Medium=y"),
+            "https://example.com/?id=3"
+        );
+        assert_eq!(
+            normalize("https://example.com/?fbclid=1&gclid=2&_ga=3"),
+            "https://example.com/"
+        );
+        assert_eq!(
+            normalize("https://example.com/?utm=1&gclid_x=2"),
+            "https://example.com/?gclid_x=2&utm=1"
+        );
+    }
+
+    #[test]
+    fn repeated_params_keep_their_order() {
+        assert_eq!(
+            normalize("https://example.com/?tag=b&page=2&tag=a&tag=c"),
+            "https://example.com/?page=2&tag=b&tag=a&tag=c"
+        );
+        assert_eq!(
+            normalize("https://example.com/?q=a%20b&a"),
+            "https://example.com/?a&q=a%20b"
+        );
+    }
+
+    #[test]
+    fn empty_queries_are_removed() {
+        assert_eq!(normalize("https://example.com/?"), "https://example.com/");
+        assert_eq!(
+            normalize("https://example.com/page?&&"),
+            "https://example.com/page"
+        );
+    }
+
+    #[test]
+    fn trailing_slashes_are_removed_but_from_the_root() {
+        assert_eq!(normalize("https://example.com"), "https://example.com/");
+        assert_eq!(
+            normalize("https://example.com/docs/"),
+            "https://example.com/docs"
+        );
+        assert_eq!(
+            normalize("https://example.com/docs//?a=1"),
+            "https://example.com/docs?a=1"
+        );
+        assert_eq!(
+            normalize("http://EXAMPLE.com:80/a/./b/../c/"),
+            "http://example.com/a/c"
+        );
+    }
+
+    #[test]
+    fn only_http_urls_are_resolved() {
+        let base = Url::parse("https://example.com/blog/post").unwrap();
+
+        assert_eq!(
+            resolve_url(&base, " ../about/#team ").as_deref(),
+            Some("https://example.com/about")
+        );
+        assert_eq!(
+            resolve_url(&base, "//cdn.example.com/x").as_deref(),
+            Some("https://cdn.example.com/x")
+        );
+        assert_eq!(resolve_url(&base, "mailto:me@example.com"), None);
+        assert_eq!(resolve_url(&base, "javascript:void(0)"), None);
+        assert_eq!(resolve_url(&base, "ftp://example.com/file"), None);
+        assert_eq!(resolve_url(&base, "data:text/plain,hi"), None);
+        assert_eq!(normalize_str("file:///etc/passwd"), None);
+        assert_eq!(normalize_str("not a url"), None);
+    }
+}
@@ -0,0 +1,184 @@
+use scraper::{ElementRef, Html, Selector};
+use super::canonical::has_rel;
+use super::robots::{product_token, CRAWLER_USER_AGENT};
+
+/// Directives of `X-Robots-Tag` headers that take a value, those aren't
+/// user-agents in `unavailable_after: ...`.
+const VALUED_DIRECTIVES: [&str; 4] = [
+    "unavailable_after",
+    "max-snippet",
+    "max-image-preview",
+    "max-video-preview"
+];
+
+/// Indexing directives of a page, set by `robots` meta tags and by the
+/// `X-Robots-Tag` header of the response:
+/// - `noindex`: The page must not be stored.
+/// - `nofollow`: Links of the page must not be used to discover pages.
+/// - `none`: Both of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RobotsDirectives {
+    pub noindex: bool,
+    pub nofollow: bool
+}
+
+impl RobotsDirectives {
+    /// Applies a comma separated list of directives, such as `noindex, follow`.
+    /// Unknown directives are ignored.
+    fn apply(&mut self, directives: &str) {
+        for directive in directives.split(',') {
+            match directive.trim().to_lowercase().as_str() {
+                "noindex" => self.noindex = true,
+                "nofollow" => self.nofollow = true,
+                "none" => {
+                    self.noindex = true;
+                    self.nofollow = true;
+                },
+                _ => ()
+            }
+        }
+    }
+
+    /// Returns the directives of a page, from `meta[name="robots"]` tags and
+    /// from the meta tags naming the crawler, such as `meta[name="joogle"]`,
+    /// then from the values of the `X-Robots-Tag` headers. Header values can
+    /// target a user-agent, such as `joogle: noindex`, those targeting other
+    /// user-agents are ignored.
+    /// INFO: Directives are only added, the most restrictive ones win.
+    pub fn from_page(page: &Html, headers: &[String]) -> Self {
+        let token = product_token(&CRAWLER_USER_AGENT);
+        let meta_selector = Selector::parse("meta[name][content]").unwrap();
+        let mut directives = Self::default();
+
+        page.select(&meta_selector)
+            .filter(|e| {
+                let name = e.attr("name").unwrap().trim().to_lowercase();
+
+                name == "robots" || name == token
+            })
+            .for_each(|e| directives.apply(e.attr("content").unwrap()));
+
+        for header in headers {
+            match header.split_once(':') {
+                Some((user_agent, value)) if !is_directive(user_agent) => {
+                    if product_token(user_agent) == token {
+                        directives.apply(value);
+                    }
+                },
+                _ => directives.apply(header)
+            }
+        }
+        directives
+    }
+
+    /// Returns the reason directives are applied to a page, for crawl logs.
+    pub fn describe(&self) -> Option<String> {
+        match (self.noindex, self.nofollow) {
+            (false, false) => None,
+            (true, false) => Some("noindex".into()),
+            (false, true) => Some("nofollow".into()),
+            (true, true) => Some("noindex, nofollow".into())
+        }
+    }
+}
+
+/// Returns true if the start of an `X-Robots-Tag` value, before a colon, is a
+/// directive and not a user-agent.
+fn is_directive(value: &str) -> bool {
+    let value = value.trim().to_lowercase();
+
+    value.contains(',') || VALUED_DIRECTIVES.contains(&value.as_str())
+}
+
+/// Returns true if a link must not be used to discover pages, as it has a
+/// `rel="nofollow"` attribute.
+pub fn is_nofollow_link(link: &ElementRef) -> bool {
+    link.attr("rel").is_some_and(|rel| has_rel(rel, "nofollow"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOINDEX: RobotsDirectives =
+        RobotsDirectives { noindex: true, nofollow: false };
+    const NOFOLLOW: RobotsDirectives =
+        RobotsDirectives { noindex: false, nofollow: true };
+    const NONE: RobotsDirectives =
+        RobotsDirectives { noindex: true, nofollow: true };
+
+    fn directives(meta: &[(&str, &str)], headers: &[&str]) -> RobotsDirectives {
+        let meta = meta.iter()
+            .map(|(name, content)| {
+                format!("<meta name=\"{name}\" content=\"{content}\">")
+            })
+            .collect::<String>();
+        let page = Html::parse_document(&format!("<head>{meta}</head>"));
+        let headers = headers.iter()
+            .map(|h| h.to_string())
+            .collect::<Vec<String>>();
+
+        RobotsDirectives::from_page(&page, &headers)
+    }
+
+    #[test]
+    fn none_is_noindex_and_nofollow() {
+        assert_eq!(directives(&[("robots", "none")], &[]), NONE);
+        assert_eq!(directives(&[], &["none"]), NONE);
+        assert_eq!(
+            directives(&[("robots", "index, follow")], &[]),
+            RobotsDirectives::default()
+        );
+    }
+
+    #[test]
+    fn meta_tags_and_headers_add_up() {
+        assert_eq!(
+            directives(&[("robots", "noindex")], &["nofollow"]),
+            NONE
+        );
+        assert_eq!(directives(&[("robots", "noindex, nofollow")], &[]), NONE);
+        assert_eq!(
+            directives(&[("robots", "nofollow")], &["index", "noarchive"]),
+            NOFOLLOW
+        );
+        assert_eq!(
+            directives(&[], &["unavailable_after: 2025-01-01", "noindex"]),
+            NOINDEX
+        );
+    }
+
+    #[test]
+    fn other_user_agents_are_ignored() {
+        let token = product_token(&CRAWLER_USER_AGENT);
+
+        assert_eq!(directives(&[], &[&format!("{token}: noindex")]), NOINDEX);
+        assert_eq!(
+            directives(&[], &["otherbot: noindex", "otherbot: nofollow"]),
+            RobotsDirectives::default()
+        );
+        assert_eq!(
+            directives(&[(&token, "nofollow"), ("otherbot", "noindex")], &[]),
+            NOFOLLOW
+        );
+    }
+
+    #[test]
+    fn directives_are_case_insensitive() {
+        let token = product_token(&CRAWLER_USER_AGENT).to_uppercase();
+
+        assert_eq!(directives(&[("ROBOTS", "NoIndex")], &[]), NOINDEX);
+        assert_eq!(directives(&[], &[" NONE "]), NONE);
+        assert_eq!(
+            directives(&[], &[&format!("{token}: NOFOLLOW")]),
+            NOFOLLOW
+        );
+        assert_eq!(directives(&[(&token, "NOINDEX")], &[]), NOINDEX);
+    }
+
+    #[test]
+    fn descriptions_name_the_directives() {
+        assert_eq!(RobotsDirectives::default().describe(), None);
+        assert_eq!(NONE.describe().as_deref(), Some("noindex, nofollow"));
+    }
+}
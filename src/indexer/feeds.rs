@@ -0,0 +1,584 @@
+use std::io::{prelude::*, BufReader};
+use std::thread;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use futures::AsyncReadExt;
+use scraper::{Html, Selector};
+use tokio::runtime::Runtime;
+use url::Url;
+use xml::reader::XmlEvent;
+use xml::EventReader;
+use crate::db::{self, feeds::FeedRecord, frontier::FrontierEntry};
+use crate::error::StdError;
+use crate::QUEUE_BOT;
+use super::canonical::{has_rel, resolve_url};
+use super::robots::CRAWLER_USER_AGENT;
+use super::url::{check_crawlable, Crawl};
+
+/// Namespace of Atom feeds, RSS feeds have no namespace.
+const ATOM_NAMESPACE: &str = "http://www.w3.org/2005/Atom";
+
+/// Content types of the feeds advertised by pages.
+const FEED_TYPES: [&str; 2] = ["application/rss+xml", "application/atom+xml"];
+
+/// Maximum size of a feed, larger feeds aren't read.
+const MAX_FEED_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Maximum number of items read from a feed.
+const MAX_FEED_ITEMS: usize = 1000;
+
+/// Bounds of the time between two polls of a feed.
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time between the polls of a feed until its update rate is known.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum time the feed bot sleeps, so that it notices new feeds.
+const FEED_IDLE_DELAY: Duration = Duration::from_secs(60);
+
+/// Whether a feed is an RSS 2.0 or an Atom feed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeedKind {
+    Rss,
+    Atom
+}
+
+/// An item of a feed, the page it links to and when it was last updated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeedItem {
+    pub link: String,
+    pub updated: Option<DateTime<Utc>>
+}
+
+/// Returns the feeds a page advertises with
+/// `link[rel="alternate"][type="application/rss+xml"]` tags, or with the Atom
+/// type. URLs are normalized, and relative ones are resolved against the URL
+/// of the page.
+pub fn discover_feeds(page: &Html, url: &Url) -> Vec<String> {
+    let link_selector = Selector::parse("link[rel][type][href]").unwrap();
+    let mut feeds: Vec<String> = vec![];
+
+    page.select(&link_selector)
+        .filter(|e| has_rel(e.attr("rel").unwrap(), "alternate"))
+        .filter(|e| {
+            let content_type = e.attr("type").unwrap().trim().to_lowercase();
+
+            FEED_TYPES.contains(&content_type.as_str())
+        })
+        .filter_map(|e| resolve_url(url, e.attr("href").unwrap()))
+        .for_each(|feed| if !feeds.contains(&feed) { feeds.push(feed) });
+    feeds
+}
+
+/// A feed, the list of its items. The kind of the feed is given by its root
+/// element, `<rss>` or `<feed>`.
+/// INFO: Only items with a valid HTTP link are kept, those are normalized,
+/// see `normalize_url`.
+pub struct FeedDefinition {
+    pub kind: FeedKind,
+    pub items: Vec<FeedItem>
+}
+
+/// An item of a feed being parsed.
+#[derive(Default)]
+struct ItemBuilder {
+    link: Option<String>,
+    guid: Option<String>,
+    guid_is_link: bool,
+    updated: Option<DateTime<Utc>>
+}
+
+impl FeedDefinition {
+    /// Parses a feed. Relative links are resolved against the URL of the feed.
+    /// INFO: RSS items link to their page with `<link>`, or with `<guid>` when
+    /// it's a permalink. Atom entries link to it with a `<link>` that has no
+    /// `rel` or `rel="alternate"`. Elements of other namespaces are ignored.
+    fn parser(
+        data: impl Read,
+        base: &Url
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let xml_feed = EventReader::new(BufReader::new(data));
+        let mut kind = None;
+        let mut items: Vec<FeedItem> = vec![];
+        let mut item: Option<ItemBuilder> = None;
+        // Local names of the open elements, `None` for foreign elements.
+        let mut nesting: Vec<Option<String>> = vec![];
+        let mut text = String::new();
+
+        for element in xml_feed {
+            match element? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let namespace = name.namespace.as_deref();
+                    let local = match kind {
+                        None => {
+                            kind = match (namespace, name.local_name.as_str()) {
+                                (None, "rss") => Some(FeedKind::Rss),
+                                (Some(ATOM_NAMESPACE), "feed") => {
+                                    Some(FeedKind::Atom)
+                                },
+                                _ => return Err(Box::new(
+                                    StdError("Not a feed".into())
+                                ))
+                            };
+                            Some(name.local_name)
+                        },
+                        Some(FeedKind::Rss) if namespace.is_none() => {
+                            Some(name.local_name)
+                        },
+                        Some(FeedKind::Atom)
+                            if namespace == Some(ATOM_NAMESPACE) =>
+                        {
+                            Some(name.local_name)
+                        },
+                        _ => None
+                    };
+                    let attr = |attr: &str| attributes.iter()
+                        .find(|a| a.name.local_name == attr)
+                        .map(|a| a.value.trim().to_string());
+                    let is_field = Self::is_item(kind, &nesting);
+
+                    nesting.push(local);
+                    text.clear();
+                    if Self::is_item(kind, &nesting) {
+                        item = Some(ItemBuilder::default());
+                        continue;
+                    }
+
+                    let Some(item) = item.as_mut().filter(|_| is_field) else {
+                        continue
+                    };
+
+                    match nesting.last().unwrap().as_deref() {
+                        Some("guid") => {
+                            item.guid_is_link = attr("isPermaLink")
+                                .is_none_or(|p| p != "false");
+                        },
+                        Some("link") if kind == Some(FeedKind::Atom) => {
+                            let rel = attr("rel");
+
+                            if item.link.is_none() &&
+                                rel.is_none_or(|r| has_rel(&r, "alternate"))
+                            {
+                                item.link = attr("href");
+                            }
+                        },
+                        _ => ()
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    let is_item = Self::is_item(kind, &nesting);
+                    let local = nesting.pop().flatten();
+                    let is_field = Self::is_item(kind, &nesting);
+
+                    if is_item {
+                        let Some(built) = item.take() else { continue };
+                        let link = built.link
+                            .or(built.guid.filter(|_| built.guid_is_link))
+                            .and_then(|link| resolve_url(base, &link));
+
+                        if let Some(link) = link {
+                            items.push(
+                                FeedItem { link, updated: built.updated }
+                            );
+                        }
+                        if items.len() >= MAX_FEED_ITEMS {
+                            break;
+                        }
+                    } else if is_field {
+                        let (Some(item), Some(field)) = (item.as_mut(), local)
+                        else {
+                            continue
+                        };
+                        let value = text.trim();
+
+                        match (kind, field.as_str()) {
+                            (Some(FeedKind::Rss), "link") => {
+                                item.link = Some(value.to_string())
+                            },
+                            (Some(FeedKind::Rss), "guid") => {
+                                item.guid = Some(value.to_string())
+                            },
+                            (Some(FeedKind::Rss), "pubDate") => {
+                                item.updated =
+                                    DateTime::parse_from_rfc2822(value).ok()
+                                        .map(|d| d.to_utc())
+                            },
+                            // The date of the last update is preferred.
+                            (Some(FeedKind::Atom), "updated" | "published") => {
+                                let date =
+                                    DateTime::parse_from_rfc3339(value).ok()
+                                        .map(|d| d.to_utc());
+
+                                if field == "updated" || item.updated.is_none()
+                                {
+                                    item.updated = date.or(item.updated);
+                                }
+                            },
+                            _ => ()
+                        }
+                    }
+                }
+                XmlEvent::Characters(data) | XmlEvent::CData(data) => {
+                    text.push_str(&data);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            kind: kind.ok_or(StdError("Empty feed".into()).to_boxed())?,
+            items
+        })
+    }
+
+    /// Returns true if the open elements end with an item of the feed, an
+    /// `<item>` of the `<channel>` of an RSS feed or an `<entry>` of an Atom
+    /// feed.
+    fn is_item(kind: Option<FeedKind>, nesting: &[Option<String>]) -> bool {
+        match (kind, nesting) {
+            (Some(FeedKind::Rss), [Some(root), Some(channel), Some(item)]) => {
+                root == "rss" && channel == "channel" && item == "item"
+            },
+            (Some(FeedKind::Atom), [Some(root), Some(entry)]) => {
+                root == "feed" && entry == "entry"
+            },
+            _ => false
+        }
+    }
+
+    /// Returns the time to wait before polling the feed again. It's the mean
+    /// time between the updates of its items, or when their dates aren't
+    /// known, the previous interval halved if new items were found and
+    /// doubled otherwise. Intervals are bounded by `MIN_POLL_INTERVAL` and
+    /// `MAX_POLL_INTERVAL`.
+    pub fn next_interval(
+        &self,
+        previous: Duration,
+        new_items: usize
+    ) -> Duration {
+        let mut dates = self.items.iter()
+            .filter_map(|i| i.updated)
+            .collect::<Vec<DateTime<Utc>>>();
+
+        dates.sort();
+
+        let interval = match (dates.first(), dates.last()) {
+            (Some(first), Some(last)) if dates.len() >= 2 => {
+                (*last - *first).to_std().unwrap_or_default() /
+                    (dates.len() - 1) as u32
+            },
+            _ if new_items > 0 => previous / 2,
+            _ => previous * 2
+        };
+        interval.clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+    }
+}
+
+/// Result of the poll of a feed.
+enum Poll {
+    /// The feed was fetched, with the validators of the response.
+    Fetched(FeedDefinition, Option<String>, Option<String>),
+    /// The feed didn't change since it was last polled.
+    NotModified
+}
+
+/// Fetches a feed, conditionally if it was polled before.
+async fn poll_feed(
+    feed: &FeedRecord
+) -> Result<Poll, Box<dyn std::error::Error>> {
+    let mut req = surf::get(&feed.url)
+        .header("User-Agent", CRAWLER_USER_AGENT.as_str());
+
+    if let Some(etag) = &feed.etag {
+        req = req.header("If-None-Match", etag.as_str());
+    }
+    if let Some(last_modified) = &feed.last_modified {
+        req = req.header("If-Modified-Since", last_modified.as_str());
+    }
+
+    let mut res = req.await?;
+
+    if res.status() == 304 {
+        return Ok(Poll::NotModified);
+    }
+    if !res.status().is_success() {
+        return Err(Box::new(
+            StdError(format!("Unsuccesful response code {}", res.status()))
+        ));
+    }
+
+    let mut body = vec![];
+
+    res.take_body().take(MAX_FEED_SIZE + 1).read_to_end(&mut body).await?;
+    if body.len() as u64 > MAX_FEED_SIZE {
+        return Err(Box::new(StdError("Feed larger than 10 MB".into())));
+    }
+
+    let definition = FeedDefinition::parser(
+        body.as_slice(), &Url::parse(&feed.url)?
+    )?;
+
+    Ok(Poll::Fetched(
+        definition,
+        res.header("ETag").map(|h| h.as_str().to_string()),
+        res.header("Last-Modified").map(|h| h.as_str().to_string())
+    ))
+}
+
+/// Polls a feed and queues the links of its new items, then schedules its
+/// next poll. Feeds that can't be fetched are polled less often.
+async fn process_feed(
+    feed: FeedRecord
+) -> Result<(), Box<dyn std::error::Error>> {
+    let slower = (feed.poll_interval * 2).min(MAX_POLL_INTERVAL);
+
+    match check_crawlable(&feed.url).await {
+        Crawl::Allowed(_) => (),
+        Crawl::Skipped(reason) | Crawl::Postponed(reason, _) => {
+            db::feeds::update_poll(&feed.url, slower, None, None)?;
+            return Err(Box::new(StdError(reason)));
+        }
+    }
+
+    match poll_feed(&feed).await {
+        Ok(Poll::Fetched(definition, etag, last_modified)) => {
+            let links = definition.items.iter()
+                .map(|i| i.link.clone())
+                .collect::<Vec<String>>();
+            let new_links = db::feed_items::replace_items(&feed.url, &links)?;
+            let interval = definition.next_interval(
+                feed.poll_interval, new_links.len()
+            );
+
+            println!(
+                "[FEEDS] {} new items in {:?} feed {}, next poll in {}s",
+                new_links.len(), definition.kind, feed.url, interval.as_secs()
+            );
+            QUEUE_BOT.queue_url(
+                new_links.into_iter().map(FrontierEntry::feed).collect()
+            );
+            db::feeds::update_poll(&feed.url, interval, etag, last_modified)?;
+        },
+        Ok(Poll::NotModified) => {
+            db::feeds::update_poll(&feed.url, slower, None, None)?;
+        },
+        Err(err) => {
+            db::feeds::update_poll(&feed.url, slower, None, None)?;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// The `FeedBot` polls the feeds discovered on indexed pages, each one on its
+/// own schedule, and sends the links of their new items to the `QueueBot`.
+pub struct FeedBot;
+
+impl FeedBot {
+    pub fn init() -> Self {
+        Self
+    }
+
+    /// Starts polling feeds. The bot sleeps until the next feed must be
+    /// polled, or for `FEED_IDLE_DELAY` at most to notice new feeds.
+    pub fn thread_bot(&self) {
+        thread::spawn(move || {
+            let rt = Runtime::new().unwrap();
+
+            loop {
+                let feed = match db::feeds::get_next_feed() {
+                    Ok(feed) => feed,
+                    Err(err) => {
+                        println!("[FEEDS] Failed to read feeds: {err}");
+                        None
+                    }
+                };
+                let now = Utc::now().timestamp_millis();
+
+                match feed {
+                    Some(feed) if feed.next_poll_at <= now => {
+                        let url = feed.url.clone();
+
+                        if let Err(err) = rt.block_on(process_feed(feed)) {
+                            println!("[FEEDS] {url}: {err}");
+                        }
+                    },
+                    // No feed must be polled yet.
+                    next => {
+                        let wait = next
+                            .map(|f| f.next_poll_at - now)
+                            .map(|ms| Duration::from_millis(ms as u64))
+                            .unwrap_or(FEED_IDLE_DELAY);
+
+                        thread::sleep(wait.min(FEED_IDLE_DELAY));
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(content: &str) -> FeedDefinition {
+        let base = Url::parse("https://example.com/blog/feed.xml").unwrap();
+
+        FeedDefinition::parser(content.as_bytes(), &base).unwrap()
+    }
+
+    fn item(link: &str, updated: Option<&str>) -> FeedItem {
+        FeedItem {
+            link: link.into(),
+            updated: updated.map(|d| {
+                DateTime::parse_from_rfc3339(d).unwrap().to_utc()
+            })
+        }
+    }
+
+    /// Returns a feed whose items were updated at the given hours of a day.
+    fn feed_updated_at(hours: &[u32]) -> FeedDefinition {
+        FeedDefinition {
+            kind: FeedKind::Atom,
+            items: hours.iter()
+                .map(|h| item(
+                    &format!("https://example.com/{h}"),
+                    Some(&format!("2024-05-01T{h:02}:00:00Z"))
+                ))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn rss_items_link_to_pages() {
+        let feed = feed(r#"<?xml version="1.0"?>
+            <rss version="2.0"
+                xmlns:atom="http://www.w3.org/2005/Atom">
+                <channel>
+                    <link>https://example.com/</link>
+                    <atom:link href="https://example.com/blog/feed.xml"
+                        rel="self" />
+                    <item>
+                        <link>https://example.com/first#comments</link>
+                        <pubDate>Wed, 01 May 2024 12:30:00 +0200</pubDate>
+                    </item>
+                    <item>
+                        <guid>https://example.com/second</guid>
+                    </item>
+                    <item>
+                        <guid isPermaLink="false">tag:example.com,1</guid>
+                    </item>
+                </channel>
+            </rss>
+        "#);
+
+        assert_eq!(feed.kind, FeedKind::Rss);
+        assert_eq!(feed.items, vec![
+            item("https://example.com/first", Some("2024-05-01T10:30:00Z")),
+            item("https://example.com/second", None)
+        ]);
+    }
+
+    #[test]
+    fn atom_entries_link_to_alternates() {
+        let feed = feed(r#"
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <link href="https://example.com/" />
+                <entry>
+                    <link rel="edit" href="https://example.com/edit/1" />
+                    <link rel="alternate" href="https://example.com/first" />
+                    <link href="https://example.com/ignored" />
+                    <published>2024-05-01T10:00:00Z</published>
+                    <updated>2024-05-02T10:00:00+02:00</updated>
+                </entry>
+                <entry>
+                    <link href="https://example.com/second" />
+                    <published>2024-05-01T10:00:00Z</published>
+                </entry>
+                <entry>
+                    <link rel="enclosure" href="https://example.com/a.mp3" />
+                </entry>
+            </feed>
+        "#);
+
+        assert_eq!(feed.kind, FeedKind::Atom);
+        assert_eq!(feed.items, vec![
+            item("https://example.com/first", Some("2024-05-02T08:00:00Z")),
+            item("https://example.com/second", Some("2024-05-01T10:00:00Z"))
+        ]);
+    }
+
+    #[test]
+    fn relative_links_are_resolved() {
+        let feed = feed(r#"
+            <rss version="2.0"><channel>
+                <item><link>posts/first</link></item>
+                <item><link>/about</link></item>
+                <item><link>mailto:me@example.com</link></item>
+            </channel></rss>
+        "#);
+
+        assert_eq!(feed.items, vec![
+            item("https://example.com/blog/posts/first", None),
+            item("https://example.com/about", None)
+        ]);
+    }
+
+    #[test]
+    fn invalid_or_missing_dates_are_unknown() {
+        let feed = feed(r#"
+            <rss version="2.0"><channel>
+                <item>
+                    <link>https://example.com/first</link>
+                    <pubDate>yesterday</pubDate>
+                </item>
+                <item><link>https://example.com/second</link></item>
+            </channel></rss>
+        "#);
+
+        assert!(feed.items.iter().all(|i| i.updated.is_none()));
+        assert_eq!(
+            feed.next_interval(DEFAULT_POLL_INTERVAL, 2),
+            DEFAULT_POLL_INTERVAL / 2
+        );
+        assert_eq!(
+            feed.next_interval(DEFAULT_POLL_INTERVAL, 0),
+            DEFAULT_POLL_INTERVAL * 2
+        );
+    }
+
+    #[test]
+    fn other_documents_arent_feeds() {
+        let base = Url::parse("https://example.com/").unwrap();
+
+        for document in ["<html></html>", "<feed></feed>", ""] {
+            let feed = FeedDefinition::parser(document.as_bytes(), &base);
+
+            assert!(feed.is_err());
+        }
+    }
+
+    #[test]
+    fn intervals_follow_updates_within_bounds() {
+        let hour = Duration::from_secs(60 * 60);
+
+        assert_eq!(
+            feed_updated_at(&[8, 2, 14]).next_interval(hour, 0),
+            6 * hour
+        );
+        assert_eq!(
+            feed_updated_at(&[10, 10, 10]).next_interval(hour, 3),
+            MIN_POLL_INTERVAL
+        );
+        assert_eq!(
+            feed_updated_at(&[10]).next_interval(MIN_POLL_INTERVAL, 1),
+            MIN_POLL_INTERVAL
+        );
+        assert_eq!(
+            feed_updated_at(&[]).next_interval(MAX_POLL_INTERVAL, 0),
+            MAX_POLL_INTERVAL
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use sha2::{Digest, Sha256};
+use url::Url;
+use crate::db;
+use crate::error::StdError;
+use super::canonical::resolve_url;
+use super::robots::CRAWLER_USER_AGENT;
+use super::url::{check_crawlable, Crawl};
+
+/// Maximum number of redirects followed to fetch a page.
+pub const MAX_REDIRECTS: usize = 10;
+
+/// A redirect followed while fetching a page, from a URL to the URL given by
+/// its `Location` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+    pub status: u16
+}
+
+impl Redirect {
+    /// Returns true if the redirect is permanent, the old URL shouldn't be
+    /// crawled anymore.
+    pub fn is_permanent(&self) -> bool {
+        self.status == 301 || self.status == 308
+    }
+}
+
+/// What is known of the last version of an indexed page, to re-crawl it
+/// conditionally: its `ETag` and `Last-Modified` headers, and the hash of its
+/// content, see `content_hash`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>
+}
+
+impl Validators {
+    /// Returns the validators of a fetched page.
+    pub fn from_response(res: &surf::Response, content: &str) -> Self {
+        Self {
+            etag: res.header("ETag").map(|h| h.as_str().to_string()),
+            last_modified: res.header("Last-Modified")
+                .map(|h| h.as_str().to_string()),
+            content_hash: Some(content_hash(content))
+        }
+    }
+}
+
+/// Returns the SHA-256 hash of the content of a page, as hexadecimal.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// A fetched page, with the URL it was finally fetched at and the redirects
+/// followed to reach it.
+pub struct Fetched {
+    pub url: String,
+    pub response: surf::Response,
+    pub redirects: Vec<Redirect>
+}
+
+/// Fetches a page, redirects are followed up to `MAX_REDIRECTS` times. Each
+/// URL a page redirects to must be allowed by the robots rules of its domain,
+/// see `check_crawlable`.
+/// URLs redirected to are normalized, see `normalize_url`.
+/// Indexed URLs are requested conditionally with their validators, the server
+/// answers `304 Not Modified` if the page didn't change.
+/// WARN: An error is returned for redirect loops, too many redirects, and
+/// redirects to URLs that can't be crawled, those are recorded as skipped.
+pub async fn fetch_page(
+    url: &str
+) -> Result<Fetched, Box<dyn std::error::Error>> {
+    let mut url = url.to_string();
+    let mut redirects: Vec<Redirect> = vec![];
+
+    loop {
+        let mut req = surf::get(url.as_str())
+            .header("User-Agent", CRAWLER_USER_AGENT.as_str());
+
+        if let Some(validators) = db::sites::get_validators(&url)? {
+            if let Some(etag) = validators.etag {
+                req = req.header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = validators.last_modified {
+                req = req.header("If-Modified-Since", last_modified);
+            }
+        }
+
+        let res = req.await?;
+        let status = res.status();
+        let location = res.header("Location").map(|l| l.as_str().to_string());
+
+        let location = match location {
+            Some(location) if status.is_redirection() => location,
+            _ => return Ok(Fetched { url, response: res, redirects })
+        };
+
+        if redirects.len() >= MAX_REDIRECTS {
+            return Err(
+                StdError(format!("Too many redirects from {url}")).to_boxed()
+            );
+        }
+
+        let target = resolve_url(&Url::parse(&url)?, &location).ok_or(
+            StdError(format!("Invalid redirect to {location}")).to_boxed()
+        )?;
+
+        if target == url || redirects.iter().any(|r| r.from == target) {
+            return Err(
+                StdError(format!("Redirect loop at {target}")).to_boxed()
+            );
+        }
+        match check_crawlable(&target).await {
+            Crawl::Allowed(_) => (),
+            Crawl::Skipped(reason) | Crawl::Postponed(reason, _) => {
+                db::skipped_urls::record(&target, &reason)?;
+                return Err(StdError(
+                    format!("Redirect to {target} skipped: {reason}")
+                ).to_boxed());
+            }
+        }
+        redirects.push(Redirect {
+            from: url,
+            to: target.clone(),
+            status: status.into()
+        });
+        url = target;
+    }
+}
@@ -1,45 +1,146 @@
 use scraper::{Html, Selector};
+use whatlang::Lang;
+use crate::analysis::primary_language;
 
 const LOW_TTR: f64 = 0.8;
-const LOW_LOC_PROB: f64 = 0.6; 
+const LOW_LOC_PROB: f64 = 0.6;
+/// Probability that a language declared by a page or its server is right, it's
+/// not certain as pages often keep the language of their template.
+const DECLARED_LOC_PROB: f64 = 0.8;
+/// Maximum number of bytes of text used to detect the language of a page.
+const MAX_DETECTION_TEXT: usize = 8192;
 
-/// This type defines a Localization, as we do not use extra structs for this 
-/// we use a custom type name to make this more readable. 
+/// This type defines a Localization, as we do not use extra structs for this
+/// we use a custom type name to make this more readable.
 /// The first part of this type is the found localization, the second part is
 /// the probability it's the right localization. It may be weird at first glance
-/// to have this probability data but when textual language detection algorithm
-/// will be implemented it will be a key information when language is not 
-/// explicitly set.
+/// to have this probability data but it's a key information when language is
+/// not explicitly set, as it's then detected from the page's text.
 /// WARN: IDK IF IT'S A GOOD IDEA TO DO THIS
 pub type Localization = (String, f64);
 
-/// To determine a website's localization, we use the following things:
+/// Returns the ISO 639-1 code of a language detected by `whatlang`, languages
+/// without one keep their ISO 639-3 code.
+fn language_code(lang: Lang) -> &'static str {
+    match lang {
+        Lang::Afr => "af", Lang::Aka => "ak", Lang::Amh => "am",
+        Lang::Ara => "ar", Lang::Aze => "az", Lang::Bel => "be",
+        Lang::Ben => "bn", Lang::Bul => "bg", Lang::Cat => "ca",
+        Lang::Ces => "cs", Lang::Cmn => "zh", Lang::Dan => "da",
+        Lang::Deu => "de", Lang::Ell => "el", Lang::Eng => "en",
+        Lang::Epo => "eo", Lang::Est => "et", Lang::Fin => "fi",
+        Lang::Fra => "fr", Lang::Guj => "gu", Lang::Heb => "he",
+        Lang::Hin => "hi", Lang::Hrv => "hr", Lang::Hun => "hu",
+        Lang::Hye => "hy", Lang::Ind => "id", Lang::Ita => "it",
+        Lang::Jav => "jv", Lang::Jpn => "ja", Lang::Kan => "kn",
+        Lang::Kat => "ka", Lang::Khm => "km", Lang::Kor => "ko",
+        Lang::Lat => "la", Lang::Lav => "lv", Lang::Lit => "lt",
+        Lang::Mal => "ml", Lang::Mar => "mr", Lang::Mkd => "mk",
+        Lang::Mya => "my", Lang::Nep => "ne", Lang::Nld => "nl",
+        Lang::Nob => "nb", Lang::Ori => "or", Lang::Pan => "pa",
+        Lang::Pes => "fa", Lang::Pol => "pl", Lang::Por => "pt",
+        Lang::Ron => "ro", Lang::Rus => "ru", Lang::Sin => "si",
+        Lang::Slk => "sk", Lang::Slv => "sl", Lang::Sna => "sn",
+        Lang::Spa => "es", Lang::Srp => "sr", Lang::Swe => "sv",
+        Lang::Tam => "ta", Lang::Tel => "te", Lang::Tgl => "tl",
+        Lang::Tha => "th", Lang::Tuk => "tk", Lang::Tur => "tr",
+        Lang::Ukr => "uk", Lang::Urd => "ur", Lang::Uzb => "uz",
+        Lang::Vie => "vi", Lang::Yid => "yi", Lang::Zul => "zu",
+        #[allow(unreachable_patterns)]
+        _ => lang.code()
+    }
+}
+
+/// Detects the language of a text from the frequency of its trigrams, the
+/// probability is the confidence of the detection.
+pub fn detect_language(text: &str) -> Option<Localization> {
+    let mut end = text.len().min(MAX_DETECTION_TEXT);
+
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    whatlang::detect(&text[..end])
+        .map(|info| (language_code(info.lang()).into(), info.confidence()))
+}
+
+/// Cleans a language tag declared by a page, such as `fr_CA` or `en, fr`. Only
+/// the first language of a list is kept, and tags that aren't languages are
+/// ignored.
+fn clean_language_tag(tag: &str) -> Option<String> {
+    let tag = tag.split(',').next()?.trim().replace('_', "-");
+    let primary = primary_language(&tag);
+    let is_language = (2..=3).contains(&primary.len()) &&
+        primary.chars().all(|c| c.is_ascii_alphabetic()) &&
+        !["mul", "und", "zxx"].contains(&primary.as_str());
+
+    if is_language { Some(tag) } else { None }
+}
+
+/// Returns the language declared by a page or its server, in order:
 /// 1. `html[lang]`
-/// 2. `meta[http-equiv="content-language"]`
+/// 2. The `hreflang` of the page among its own alternates.
+/// 3. `meta[http-equiv="content-language"]`
+/// 4. `meta[name="language"]`
+/// 5. The `Content-Language` header of the response.
+fn declared_language(
+    page: &Html,
+    hreflang: Option<&str>,
+    header: Option<&str>
+) -> Option<String> {
+    let html_selector = Selector::parse("html[lang]").unwrap();
+    let meta_selector = Selector::parse(
+        "meta[http-equiv='content-language' i], meta[name='language' i]"
+    ).unwrap();
+    let html = page.select(&html_selector)
+        .filter_map(|e| e.attr("lang"));
+    let meta = page.select(&meta_selector)
+        .filter_map(|e| e.attr("content"));
+
+    html.chain(hreflang)
+        .chain(meta)
+        .chain(header)
+        .find_map(clean_language_tag)
+}
+
+/// To determine a website's localization, the language declared by the page
+/// or its server, see `declared_language`, is compared to the language
+/// detected from its text:
+/// - When both agree, the declared localization is kept as it may hold a
+///   region, and it's almost certain.
+/// - When those disagree, the most probable one is kept.
+/// - When there is no signal at all, `en-US` is used with a probability of 0.
 ///
-/// INFO: The following techniques should be implemented ASAP:
-/// TODO: `hreflang` for external pages
-pub fn get_localization(page: Html) -> Localization {
+/// INFO: `hreflang` is the language a page declares for itself among its
+/// alternates, see `indexer::alternates`.
+pub fn get_localization(
+    page: &Html,
+    header: Option<&str>,
+    hreflang: Option<&str>,
+    text: &str
+) -> Localization {
     let default: Localization = ("en-US".into(), 0.0);
-    let html_selector = Selector::parse("html").unwrap();
-    let meta_selector = Selector::parse("meta[http-equiv='content_language']")
-        .unwrap();
-    let html = page.select(&html_selector).collect::<Vec<_>>();
-    let meta = page.select(&meta_selector).collect::<Vec<_>>();
+    let declared = declared_language(page, hreflang, header);
+    let detected = detect_language(text);
 
-    if html.len() < 1 || meta.len() < 1 {
-        return default;
-    }
-    if let Some(lang) = html.get(0).unwrap().attr("lang") {
-        return (lang.into(), 1.0);
-    }
-    if let Some(lang) = meta.get(0).unwrap().attr("lang") {
-        return (lang.into(), 1.0);
+    match (declared, detected) {
+        (Some(declared), Some((detected, confidence))) => {
+            if primary_language(&declared) == detected {
+                let prob = 1.0 - (1.0 - DECLARED_LOC_PROB) * (1.0 - confidence);
+
+                (declared, prob)
+            } else if confidence > DECLARED_LOC_PROB {
+                (detected, confidence)
+            } else {
+                (declared, DECLARED_LOC_PROB * (1.0 - confidence))
+            }
+        },
+        (Some(declared), None) => (declared, DECLARED_LOC_PROB),
+        (None, Some(detected)) => detected,
+        (None, None) => default
     }
-    default
 }
 
-/// Automatically determine if the localization found should be used or the 
+/// Automatically determine if the localization found should be used or the
 /// default localization should be used. It works by determining the TTR we
 /// already use for indexation. If the probability AND the TTR are low, the
 /// default localization will be used.
@@ -49,3 +150,76 @@ pub fn auto_choose_localization(loc: Localization, ttr: f64) -> Localization {
     }
     loc
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog while \
+        the farmer watches from the porch and drinks his morning coffee.";
+    const FRENCH: &str = "Le renard brun rapide saute par-dessus le chien \
+        paresseux pendant que le fermier regarde depuis la terrasse.";
+
+    fn page(lang: &str) -> Html {
+        Html::parse_document(&format!(
+            "<html lang=\"{lang}\"><body></body></html>"
+        ))
+    }
+
+    #[test]
+    fn agreeing_languages_are_almost_certain() {
+        let (loc, prob) = get_localization(&page("en"), None, None, ENGLISH);
+
+        assert_eq!(loc, "en");
+        assert!(prob > DECLARED_LOC_PROB);
+    }
+
+    #[test]
+    fn regions_are_kept_when_languages_agree() {
+        let (loc, _) = get_localization(&page("en-GB"), None, None, ENGLISH);
+        assert_eq!(loc, "en-GB");
+
+        let empty = Html::parse_document("<p></p>");
+        let (loc, _) = get_localization(&empty, Some("en_GB"), None, ENGLISH);
+        assert_eq!(loc, "en-GB");
+    }
+
+    #[test]
+    fn conflicting_languages_keep_the_most_probable() {
+        let (loc, prob) = get_localization(&page("en"), None, None, FRENCH);
+        assert_eq!((loc.as_str(), prob), ("fr", 1.0));
+
+        // Too few letters to be sure of anything.
+        let (loc, prob) = get_localization(&page("en"), None, None, "ok");
+        assert_eq!(loc, "en");
+        assert!(prob < DECLARED_LOC_PROB);
+    }
+
+    #[test]
+    fn html_lang_comes_before_the_header() {
+        let (loc, _) = get_localization(&page("fr"), Some("en"), None, FRENCH);
+        assert_eq!(loc, "fr");
+
+        let (loc, _) = get_localization(&page("und"), Some("fr"), None, "");
+        assert_eq!(loc, "fr");
+    }
+
+    #[test]
+    fn short_texts_rely_on_the_declared_language() {
+        let empty = Html::parse_document("<p></p>");
+
+        assert_eq!(detect_language(""), None);
+        assert_eq!(
+            get_localization(&page("de-AT"), None, None, ""),
+            ("de-AT".into(), DECLARED_LOC_PROB)
+        );
+        assert_eq!(
+            get_localization(&empty, None, None, ""),
+            ("en-US".into(), 0.0)
+        );
+
+        let (loc, _) = get_localization(&page("de"), None, None, "ok");
+        assert_eq!(loc, "de");
+        assert_eq!(auto_choose_localization((loc, 0.1), 0.5).0, "en-US");
+    }
+}
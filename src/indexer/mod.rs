@@ -1,4 +1,9 @@
 pub mod url;
 pub mod robots;
 pub mod sitemaps;
+pub mod feeds;
 pub mod localization;
+pub mod alternates;
+pub mod directives;
+pub mod canonical;
+pub mod fetch;
@@ -1,106 +1,502 @@
-use std::{collections::HashMap, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+use std::{collections::HashMap, env, str::FromStr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rusqlite::OptionalExtension;
 use url::Url;
-use crate::{db::domains, sanitize::{sql_decode_uas, sql_escape_ap}, DB_POOL};
+use crate::{db::domains, error::StdError, sanitize::sql_decode_uas, DB_POOL};
 
-/// We need a custom robots.txt parser as none exists for Rust. The convention
-/// for robots.txt is fairly easy so it's should not be any implementation
-/// issues.
+lazy_static! {
+    /// User-Agent sent by the crawler, robots rules are matched against its
+    /// product token, `Joogle` by default.
+    pub static ref CRAWLER_USER_AGENT: String = env::var("CRAWLER_USER_AGENT")
+        .ok()
+        .filter(|ua| !product_token(ua).is_empty())
+        .unwrap_or("Joogle".into());
+    /// Delay between two requests to the same host when its robots file sets
+    /// no `Crawl-delay`, in seconds.
+    pub static ref DEFAULT_CRAWL_DELAY: Duration = env::var("CRAWL_DELAY")
+        .ok()
+        .and_then(|d| d.parse::<f64>().ok())
+        .and_then(|d| Duration::try_from_secs_f64(d).ok())
+        .unwrap_or(Duration::from_secs(1))
+        .min(MAX_CRAWL_DELAY);
+    /// Time robots rules are cached before being fetched again, in seconds.
+    pub static ref ROBOTS_TTL: Duration = env::var("ROBOTS_TTL")
+        .ok()
+        .and_then(|d| d.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(24 * 60 * 60));
+}
+
+/// Maximum number of redirects followed to fetch a robots file, the file is
+/// considered missing past it.
+const MAX_ROBOTS_REDIRECTS: usize = 5;
+
+/// Maximum delay between two requests to the same host, longer `Crawl-delay`
+/// values would stall the crawl of a host.
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(60);
+
+/// We need a custom robots.txt parser as none exists for Rust. It follows
+/// RFC 9309, rules of a user-agent are kept under its lowercased product token
+/// and groups of the same user-agent are merged. Paths of rules are stored
+/// percent-encoded, see `encode_path`. The `Crawl-delay` of a user-agent is
+/// kept in seconds.
 /// Sitemaps declared in the robots file are not saved on the database.
 pub struct RobotsDefinition {
     pub domain: String,
     pub uas_disallow: HashMap<String, Vec<String>>,
     pub uas_allow: HashMap<String, Vec<String>>,
+    pub crawl_delays: HashMap<String, f64>,
     pub sitemaps: Vec<String>
 }
 
+/// Returns the product token of a user-agent, lowercased, such as `joogle` for
+/// `Joogle/1.0`. The `*` user-agent is kept as-is.
+pub fn product_token(user_agent: &str) -> String {
+    let user_agent = user_agent.trim();
+
+    if user_agent.starts_with('*') {
+        return "*".into();
+    }
+    user_agent
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Percent-encodes a path for it to be compared with rules, as required by the
+/// RFC: octets outside of ASCII are encoded, and encoded unreserved characters
+/// are decoded. Hex digits are uppercased.
+/// INFO: Commas and spaces are encoded as well, those separate rules where
+/// they are stored, see `sql_encode_uas`.
+pub fn encode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut encoded = String::with_capacity(path.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (byte, escaped) {
+            (b'%', Some(octet)) => {
+                if octet.is_ascii_alphanumeric() || b"-._~".contains(&octet) {
+                    encoded.push(octet as char);
+                } else {
+                    encoded.push_str(&format!("%{octet:02X}"));
+                }
+                i += 3;
+                continue;
+            },
+            (b' ' | b',', _) => encoded.push_str(&format!("%{byte:02X}")),
+            _ if byte.is_ascii_graphic() => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}"))
+        }
+        i += 1;
+    }
+    encoded
+}
+
+/// Returns true if a rule matches a path. Rules match the start of paths, a
+/// `*` matches any sequence of characters and a `$` at the end of a rule
+/// matches the end of paths.
+fn rule_matches(rule: &str, path: &str) -> bool {
+    let (rule, anchored) = match rule.strip_suffix('$') {
+        Some(rule) => (rule, true),
+        None => (rule, false)
+    };
+    let parts = rule.split('*').collect::<Vec<&str>>();
+    let (first, rest) = parts.split_first().unwrap();
+
+    let Some(mut remaining) = path.strip_prefix(first) else {
+        return false;
+    };
+    if rest.is_empty() {
+        return !anchored || remaining.is_empty();
+    }
+    // Every part but the last one is matched as early as possible, leaving as
+    // much of the path as possible to the next parts.
+    let (last, middle) = rest.split_last().unwrap();
+
+    for part in middle {
+        match remaining.find(part) {
+            Some(i) => remaining = &remaining[i + part.len()..],
+            None => return false
+        }
+    }
+    if anchored {
+        remaining.ends_with(last)
+    } else {
+        remaining.contains(last)
+    }
+}
+
 impl RobotsDefinition {
     pub async fn from_domain(
         domain: String
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let mut url = Url::from_str(&domain)?;
         url.set_scheme("https").unwrap();
-        url.set_path("robots.txt");
 
-        let content = surf::get(&url.to_string()).recv_string().await?;
-        let mut curr_ua = String::new();
+        Self::from_url(&url).await
+    }
+
+    /// Fetches the robots file of the origin of a URL, following the RFC for the
+    /// status of the response:
+    /// - 2xx: The file is parsed.
+    /// - 3xx: Redirects are followed, up to `MAX_ROBOTS_REDIRECTS`.
+    /// - 4xx: There is no file, everything is allowed.
+    /// - 5xx: The file is unreachable, an error is returned.
+    ///
+    /// INFO: An error means that nothing may be crawled until the file can be
+    /// fetched. The `429 Too Many Requests` status is handled as a 5xx.
+    pub async fn from_url(
+        url: &Url
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let domain = url.domain().unwrap().to_string();
+        let mut robots_url = url.join("/robots.txt")?;
+
+        for _ in 0..=MAX_ROBOTS_REDIRECTS {
+            let mut res = surf::get(robots_url.as_str())
+                .header("User-Agent", CRAWLER_USER_AGENT.as_str())
+                .await?;
+            let status = res.status();
+            let location = res.header("Location")
+                .filter(|_| status.is_redirection())
+                .map(|l| l.as_str().to_string());
+
+            if status.is_success() {
+                return Ok(Self::parse(domain, &res.body_string().await?));
+            }
+            if let Some(location) = location {
+                robots_url = robots_url.join(&location)?;
+                continue;
+            }
+            if status.is_server_error() || status == 429 {
+                return Err(Box::new(StdError(
+                    format!("robots.txt is unreachable ({status})")
+                )));
+            }
+            return Ok(Self::parse(domain, ""));
+        }
+        println!("[ROBOTS] Too many redirects for {domain}, allowing all");
+        Ok(Self::parse(domain, ""))
+    }
+
+    /// Returns the robots rules of the domain of a URL. Rules are read from the
+    /// database, and fetched then saved on the first contact with a domain or
+    /// once they are older than `ROBOTS_TTL`.
+    pub async fn for_url(
+        url: &Url
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let domain = url.domain().unwrap_or("").to_string();
+
+        if let Some(robots) = Self::from_db(domain)? {
+            return Ok(robots);
+        }
+        let robots = Self::from_url(url).await?;
+
+        robots.db_save()?;
+        Ok(robots)
+    }
+
+    /// Returns true if the crawler may crawl a URL, see `is_allowed`.
+    pub fn is_allowed_url(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string()
+        };
+
+        self.is_allowed(&CRAWLER_USER_AGENT, &path)
+    }
+
+    /// Parses the content of a robots file. A group starts with one or more
+    /// `User-agent` lines and holds the rules following them, rules outside of
+    /// groups are ignored, as are invalid lines.
+    pub fn parse(domain: String, content: &str) -> Self {
         let mut uas_allow: HashMap<String, Vec<String>> = HashMap::new();
         let mut uas_disallow: HashMap<String, Vec<String>> = HashMap::new();
+        let mut crawl_delays: HashMap<String, f64> = HashMap::new();
         let mut sitemaps = vec![];
+        let mut group: Vec<String> = vec![];
+        // A `User-agent` line following rules starts a new group.
+        let mut in_rules = false;
 
-        content
-            .lines()
-            .filter(|line| !line.is_empty() && !line.starts_with("#"))
-            .for_each(|line| {
-                let (directive, value) = line.split_once(":").unwrap();
-                let directive = directive.to_lowercase();
-                let value = value.replace(" ", "");
-
-                match directive.as_str() {
-                    "user-agent" => { curr_ua = value; }
-                    "allow" => {
-                        if let Some(ua_ref) = uas_allow.get_mut(&curr_ua) {
-                            ua_ref.push(value);
-                        } else {
-                            uas_allow.insert(curr_ua.clone(), vec![value]);
-                        }
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            let Some((directive, value)) = line.split_once(':') else {
+                continue
+            };
+            let value = value.trim();
+
+            match directive.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    let user_agent = product_token(value);
+
+                    if in_rules {
+                        group.clear();
+                        in_rules = false;
+                    }
+                    if user_agent.is_empty() {
+                        continue;
+                    }
+                    // User-agents with no rules still have a group, which
+                    // allows everything.
+                    uas_allow.entry(user_agent.clone()).or_default();
+                    uas_disallow.entry(user_agent.clone()).or_default();
+                    group.push(user_agent);
+                },
+                directive @ ("allow" | "disallow") => {
+                    in_rules = true;
+                    // An empty rule matches nothing.
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let rules = if directive == "allow" {
+                        &mut uas_allow
+                    } else {
+                        &mut uas_disallow
+                    };
+
+                    for user_agent in &group {
+                        rules.get_mut(user_agent).unwrap()
+                            .push(encode_path(value));
                     }
-                    "disallow" => {
-                        if let Some(ua_ref) = uas_disallow.get_mut(&curr_ua) {
-                            ua_ref.push(value);
-                        } else {
-                            uas_disallow.insert(curr_ua.clone(), vec![value]);
+                },
+                "crawl-delay" => {
+                    in_rules = true;
+                    let Ok(delay) = value.parse::<f64>() else { continue };
+
+                    if delay.is_finite() && delay >= 0.0 {
+                        for user_agent in &group {
+                            crawl_delays.insert(user_agent.clone(), delay);
                         }
                     }
-                    "sitemap" => { sitemaps.push(value); }
-                    _ => ()
-                }
-            });
+                },
+                "sitemap" if !value.is_empty() => {
+                    sitemaps.push(value.to_string());
+                },
+                _ => ()
+            }
+        }
+
+        Self { domain, uas_disallow, uas_allow, crawl_delays, sitemaps }
+    }
+
+    /// Returns the group of a user-agent, `*` if it has none.
+    fn group(&self, user_agent: &str) -> String {
+        let user_agent = product_token(user_agent);
 
-        Ok(Self { 
-            domain: url.domain().unwrap().into(), 
-            uas_disallow, 
-            uas_allow, 
-            sitemaps 
-        })
+        if self.uas_allow.contains_key(&user_agent) ||
+            self.uas_disallow.contains_key(&user_agent)
+        {
+            user_agent
+        } else {
+            "*".into()
+        }
     }
 
-    /// TODO: Improve this function's efficiency.
+    /// Returns the `Crawl-delay` of the group of a user-agent, if any.
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.crawl_delays.get(&self.group(user_agent))
+            .and_then(|delay| Duration::try_from_secs_f64(*delay).ok())
+            .map(|delay| delay.min(MAX_CRAWL_DELAY))
+    }
+
+    /// Returns true if a user-agent may crawl a path, which should hold the
+    /// query of the URL if any. The rules of the group of the user-agent are
+    /// used, or those of the `*` group if it has none.
+    /// The longest rule matching the path takes precedence, `Allow` rules
+    /// winning over `Disallow` rules of the same length. Paths matching no rule
+    /// are allowed, as is `/robots.txt`.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        let key = self.group(user_agent);
+        let path = encode_path(if path.is_empty() { "/" } else { path });
+
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        let longest = |rules: Option<&Vec<String>>| rules
+            .into_iter()
+            .flatten()
+            .filter(|rule| rule_matches(rule, &path))
+            .map(|rule| rule.len())
+            .max();
+        let allow = longest(self.uas_allow.get(&key));
+        let disallow = longest(self.uas_disallow.get(&key));
+
+        match (allow, disallow) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow
+        }
+    }
+
+    /// Returns the robots rules of a domain saved on the database, or `None` if
+    /// its robots file was never fetched or if its rules expired.
     /// WARN: Using this function to recover a domain's data will not recover
     /// sitemaps.
-    pub fn from_db(domain: String) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn from_db(
+        domain: String
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
         let conn = DB_POOL.clone().get().unwrap();
-        let domain = sql_escape_ap(domain);
-        let mut select_stmt = conn.prepare(&format!("
-            SELECT uas_allow, uas_disallow
-            FROM domains
-            WHERE domain = '{domain}'
-        "))?;
-        let domain_iter = select_stmt
-            .query_map([], |row| Ok((
-                row.get::<usize, String>(0).unwrap(),
-                row.get::<usize, String>(1).unwrap()
-            )))?
-            .map(|d| d.unwrap())
-            .collect::<Vec<(String, String)>>();
-        let (uas_allow, uas_disallow) = domain_iter.get(0).unwrap();
-
-        Ok(Self {
+        let expired_before = SystemTime::now()
+            .checked_sub(*ROBOTS_TTL)
+            .unwrap_or(UNIX_EPOCH)
+            .duration_since(UNIX_EPOCH)?
+            .as_millis();
+        let uas = conn
+            .query_row("
+                SELECT
+                    COALESCE(uas_allow, ''),
+                    COALESCE(uas_disallow, ''),
+                    COALESCE(crawl_delays, '')
+                FROM domains
+                WHERE domain = ?1 AND last_robots_txt_visit > MAX(?2, 0)
+            ", params![domain, expired_before as i64], |row| Ok((
+                row.get::<usize, String>(0)?,
+                row.get::<usize, String>(1)?,
+                row.get::<usize, String>(2)?
+            )))
+            .optional()?;
+
+        Ok(uas.map(|(uas_allow, uas_disallow, crawl_delays)| Self {
             domain,
-            uas_allow: sql_decode_uas(uas_allow.to_string()),
-            uas_disallow: sql_decode_uas(uas_disallow.to_string()),
+            uas_allow: sql_decode_uas(uas_allow),
+            uas_disallow: sql_decode_uas(uas_disallow),
+            crawl_delays: sql_decode_uas(crawl_delays)
+                .into_iter()
+                .filter_map(|(ua, delay)| {
+                    Some((ua, delay.first()?.parse().ok()?))
+                })
+                .collect(),
             sitemaps: vec![]
-        })
+        }))
     }
 
+    /// Saves the rules on the database, other data of the domain is kept.
     pub fn db_save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        domains::create_row(
+        domains::update_robots(
             self.domain.clone(),
             SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
-            0,
             self.uas_allow.clone(), 
             self.uas_disallow.clone(),
-            "null".to_string()
+            self.crawl_delays.iter()
+                .map(|(ua, delay)| (ua.clone(), vec![delay.to_string()]))
+                .collect()
         )?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robots(content: &str) -> RobotsDefinition {
+        RobotsDefinition::parse("example.com".into(), content)
+    }
+
+    #[test]
+    fn longest_match_wins() {
+        let rules = robots("user-agent: *\nallow: /p\ndisallow: /\n");
+
+        assert!(rules.is_allowed("Joogle", "/page"));
+        assert!(!rules.is_allowed("Joogle", "/other"));
+
+        let rules = robots("user-agent: *\nallow: /page\ndisallow: /*.html\n");
+
+        assert!(!rules.is_allowed("Joogle", "/page.html"));
+        assert!(rules.is_allowed("Joogle", "/page.php"));
+    }
+
+    #[test]
+    fn allow_wins_ties() {
+        let rules = robots(
+            "user-agent: *\nallow: /folder\ndisallow: /folder\n"
+        );
+
+        assert!(rules.is_allowed("Joogle", "/folder/page"));
+    }
+
+    #[test]
+    fn wildcards_and_end_anchors() {
+        let rules = robots("user-agent: *\nallow: /$\ndisallow: /\n");
+
+        assert!(rules.is_allowed("Joogle", "/"));
+        assert!(!rules.is_allowed("Joogle", "/page.htm"));
+
+        let rules = robots("user-agent: *\ndisallow: /*.gif$\n");
+
+        assert!(!rules.is_allowed("Joogle", "/images/cat.gif"));
+        assert!(rules.is_allowed("Joogle", "/images/cat.gif?size=2"));
+        assert!(rules.is_allowed("Joogle", "/images/cat.gifs"));
+
+        let rules = robots("user-agent: *\ndisallow: /a*b*c\n");
+
+        assert!(!rules.is_allowed("Joogle", "/a-b-c-d"));
+        assert!(rules.is_allowed("Joogle", "/a-c-b"));
+    }
+
+    #[test]
+    fn percent_encoded_paths_are_equivalent() {
+        let rules = robots(
+            "user-agent: *\n\
+            disallow: /foo/bar/ツ\n\
+            disallow: /baz/%E3%83%84\n\
+            disallow: /qux/%62%61%7A\n"
+        );
+
+        assert!(!rules.is_allowed("Joogle", "/foo/bar/%E3%83%84"));
+        assert!(!rules.is_allowed("Joogle", "/foo/bar/%e3%83%84"));
+        assert!(!rules.is_allowed("Joogle", "/baz/ツ"));
+        assert!(!rules.is_allowed("Joogle", "/qux/baz"));
+        assert!(rules.is_allowed("Joogle", "/foo/bar/baz"));
+    }
+
+    #[test]
+    fn consecutive_user_agents_share_a_group() {
+        let rules = robots(
+            "user-agent: ExampleBot\n\
+            user-agent: Joogle/1.0\n\
+            disallow: /private\n\
+            \n\
+            user-agent: OtherBot\n\
+            disallow: /other\n\
+            \n\
+            user-agent: joogle\n\
+            crawl-delay: 2\n\
+            disallow: /tmp\n"
+        );
+
+        assert!(!rules.is_allowed("ExampleBot", "/private"));
+        assert!(!rules.is_allowed("Joogle", "/private"));
+        assert!(!rules.is_allowed("Joogle", "/tmp"));
+        assert!(rules.is_allowed("Joogle", "/other"));
+        assert!(rules.is_allowed("ExampleBot", "/tmp"));
+        assert!(!rules.is_allowed("OtherBot", "/other"));
+        assert!(rules.is_allowed("OtherBot", "/private"));
+        assert_eq!(rules.crawl_delay("Joogle"), Some(Duration::from_secs(2)));
+        assert_eq!(rules.crawl_delay("ExampleBot"), None);
+    }
+
+    #[test]
+    fn user_agents_without_group_fall_back_to_star() {
+        let rules = robots(
+            "user-agent: *\n\
+            disallow: /\n\
+            \n\
+            user-agent: Joogle\n\
+            disallow: /private\n"
+        );
+
+        assert!(rules.is_allowed("Joogle", "/page"));
+        assert!(!rules.is_allowed("Joogle", "/private"));
+        assert!(!rules.is_allowed("UnknownBot", "/page"));
+        assert!(rules.is_allowed("UnknownBot", "/robots.txt"));
+        assert!(robots("").is_allowed("Joogle", "/page"));
+    }
+}
@@ -1,158 +1,842 @@
-use std::{io::{prelude::*, BufReader}, sync::{Arc, Mutex}, thread};
+use std::io::{self, prelude::*, BufReader, Cursor};
+use std::sync::{Arc, Condvar, Mutex};
+use std::{env, thread, time::Duration};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use flate2::bufread::MultiGzDecoder;
+use futures::{AsyncRead, AsyncReadExt};
 use tokio::runtime::Runtime;
+use url::Url;
 use xml::reader::XmlEvent;
 use xml::EventReader;
-use crate::{debug::gatherers::TimingGatherer, ifcfg, ifncfg, QUEUE_BOT, SITEMAP_BOT};
+use crate::{debug::gatherers::TimingGatherer, ifcfg, QUEUE_BOT};
+use crate::db::{self, frontier::FrontierEntry};
+use crate::error::StdError;
+use super::canonical::normalize_str;
+use super::robots::CRAWLER_USER_AGENT;
 
-/// Goes through a sitemap and index content of websites depending on those
-/// sitemaps. If a sitemap links to another sitemap, this other sitemap will
-/// be analyzed.
-/// If a sitemap references other sitemaps, it means that this sitemap is an 
-/// index.
+lazy_static! {
+    /// Time before a sitemap is read again, in seconds.
+    static ref SITEMAP_REFRESH: Duration = env::var("SITEMAP_REFRESH")
+        .ok()
+        .and_then(|d| d.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(24 * 60 * 60));
+}
+
+/// Delay before reading a sitemap again when it couldn't be read.
+const SITEMAP_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Namespaces of the Sitemaps protocol, elements of other namespaces such as
+/// `image:loc` are extensions and are ignored.
+const SITEMAP_NAMESPACES: [&str; 3] = [
+    "http://www.sitemaps.org/schemas/sitemap/0.9",
+    "https://www.sitemaps.org/schemas/sitemap/0.9",
+    "http://www.google.com/schemas/sitemap/0.84"
+];
+
+/// Maximum number of entries of a sitemap, set by the Sitemaps protocol.
+const MAX_SITEMAP_ENTRIES: usize = 50000;
+
+/// Maximum size of a sitemap, set by the Sitemaps protocol. It's enforced on
+/// both compressed and uncompressed sitemaps.
+const MAX_SITEMAP_SIZE: u64 = 50 * 1024 * 1024;
+
+/// First bytes of gzip files.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Bytes of tar files after their first file name, for POSIX and GNU tarballs.
+const TAR_MAGIC: [u8; 5] = *b"ustar";
+const TAR_MAGIC_START: usize = 257;
+const TAR_MAGIC_END: usize = TAR_MAGIC_START + TAR_MAGIC.len();
+
+/// Byte order mark some XML documents start with.
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+
+/// Priority of entries that don't set one, set by the Sitemaps protocol.
+const DEFAULT_PRIORITY: f32 = 0.5;
+
+/// Share of the crawl priority of an entry given by its `<changefreq>`, the
+/// rest is given by its `<priority>`.
+const CHANGEFREQ_WEIGHT: f32 = 0.2;
+
+/// Whether a sitemap lists pages (`<urlset>`) or other sitemaps
+/// (`<sitemapindex>`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SitemapKind {
+    UrlSet,
+    Index
+}
+
+impl SitemapKind {
+    /// Returns the name of the root element of this kind of sitemap.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UrlSet => "urlset",
+            Self::Index => "sitemapindex"
+        }
+    }
+}
+
+/// How often a page is likely to change, as declared by its sitemap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeFreq {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never
+}
+
+impl ChangeFreq {
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "always" => Some(Self::Always),
+            "hourly" => Some(Self::Hourly),
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            "monthly" => Some(Self::Monthly),
+            "yearly" => Some(Self::Yearly),
+            "never" => Some(Self::Never),
+            _ => None
+        }
+    }
+
+    /// Returns how urgent it is to crawl a page changing this often, from 0
+    /// for pages that never change to 1 for pages that always do.
+    fn urgency(&self) -> f32 {
+        match self {
+            Self::Always => 1.,
+            Self::Hourly => 5. / 6.,
+            Self::Daily => 4. / 6.,
+            Self::Weekly => 0.5,
+            Self::Monthly => 2. / 6.,
+            Self::Yearly => 1. / 6.,
+            Self::Never => 0.
+        }
+    }
+}
+
+/// An entry of a sitemap, a page of a `<urlset>` or a sitemap of a
+/// `<sitemapindex>`. Only `loc` and `lastmod` are used by sitemap indexes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: String,
+    pub lastmod: Option<DateTime<Utc>>,
+    pub changefreq: Option<ChangeFreq>,
+    pub priority: Option<f32>
+}
+
+impl SitemapEntry {
+    fn new(loc: String) -> Self {
+        Self { loc, lastmod: None, changefreq: None, priority: None }
+    }
+
+    /// Sets a field of the entry from the text of its element, invalid values
+    /// are ignored.
+    fn set_field(&mut self, field: &str, value: &str) {
+        match field {
+            "loc" => self.loc = value.to_string(),
+            "lastmod" => self.lastmod = parse_lastmod(value),
+            "changefreq" => self.changefreq = ChangeFreq::parse(value),
+            "priority" => self.priority = value.parse::<f32>().ok()
+                .filter(|p| (0.0..=1.0).contains(p)),
+            _ => ()
+        }
+    }
+
+    /// Returns the priority to crawl the entry with, from 0 to 1. It's mostly
+    /// its `<priority>`, pages changing more often by their `<changefreq>` are
+    /// crawled a bit sooner.
+    /// INFO: Entries without a `<changefreq>` are considered changing weekly.
+    pub fn crawl_priority(&self) -> f32 {
+        let priority = self.priority.unwrap_or(DEFAULT_PRIORITY);
+        let urgency = self.changefreq.map_or(0.5, |c| c.urgency());
+
+        (1. - CHANGEFREQ_WEIGHT) * priority + CHANGEFREQ_WEIGHT * urgency
+    }
+}
+
+/// Parses a date in the W3C Datetime format used by sitemaps, such as
+/// `2024-05-01`, `2024-05-01T12:30+02:00` or `2024-05-01T12:30:00Z`.
+fn parse_lastmod(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.to_utc());
+    }
+    if let Ok(date) = DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M%:z") {
+        return Some(date.to_utc());
+    }
+
+    let date = match value.len() {
+        4 => NaiveDate::parse_from_str(&format!("{value}-01-01"), "%Y-%m-%d"),
+        7 => NaiveDate::parse_from_str(&format!("{value}-01"), "%Y-%m-%d"),
+        _ => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+    };
+    date.ok().map(|d| d.and_time(NaiveTime::MIN).and_utc())
+}
+
+/// Format of a sitemap file, detected from its first bytes. Compressed files
+/// and archives hold sitemaps of other formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SitemapFormat {
+    Gzip,
+    Tar,
+    Xml,
+    /// A URL per line, the content type must be `text/plain` or the file must
+    /// not start like an XML document.
+    Text
+}
+
+impl SitemapFormat {
+    /// Detects the format of a file from its first bytes and its content type.
+    /// Returns the reader to use to read the whole file, as the first bytes
+    /// are consumed.
+    fn detect<'a>(
+        mut reader: Box<dyn Read + 'a>,
+        content_type: Option<&str>
+    ) -> Result<(Self, Box<dyn Read + 'a>), Box<dyn std::error::Error>> {
+        let mut head = vec![];
+
+        reader.by_ref().take(TAR_MAGIC_END as u64).read_to_end(&mut head)?;
+
+        let first = head.iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .map(|i| &head[i..])
+            .unwrap_or(&[]);
+        let format = if head.starts_with(&GZIP_MAGIC) {
+            Self::Gzip
+        } else if head.get(TAR_MAGIC_START..) == Some(TAR_MAGIC.as_slice()) {
+            Self::Tar
+        } else if content_type == Some("text/plain") {
+            Self::Text
+        } else if first.starts_with(b"<") || first.starts_with(&UTF8_BOM) {
+            Self::Xml
+        } else {
+            Self::Text
+        };
+
+        Ok((format, Box::new(Cursor::new(head).chain(reader))))
+    }
+}
+
+/// Reads an asynchronous body synchronously, as the XML parser needs.
+/// WARN: Each read blocks the thread until data is received.
+struct BlockingReader<R>(R);
+
+impl<R: AsyncRead + Unpin> Read for BlockingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        futures::executor::block_on(self.0.read(buf))
+    }
+}
+
+/// Fails to read more than `MAX_SITEMAP_SIZE` bytes, instead of silently
+/// stopping like `Read::take`.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64
+}
+
+impl<R> LimitedReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, remaining: MAX_SITEMAP_SIZE }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            // The file may end exactly at the limit.
+            return match self.inner.read(&mut [0])? {
+                0 => Ok(0),
+                _ => Err(io::Error::other("Sitemap larger than 50 MB"))
+            };
+        }
+
+        let max = buf.len().min(self.remaining as usize);
+        let read = self.inner.read(&mut buf[..max])?;
+
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// A sitemap, a list of pages to crawl or of other sitemaps depending on its
+/// root element, see `SitemapKind`.
+/// INFO: Only entries with a valid HTTP `<loc>` URL are kept, those are
+/// normalized, see `normalize_url`.
 pub struct SitemapDefinition {
-    pub outgoing_urls: Vec<String>,
-    pub is_index: bool
+    pub kind: SitemapKind,
+    pub entries: Vec<SitemapEntry>
 }
 
 impl SitemapDefinition {
-    /// Load a sitemap depending on if it's a tarball or an xml document. This
-    /// function does not need the `tar_gz_sitemaps` experimental feature to 
-    /// be enabled to work. However, it will throw an error if a tarball url is
-    /// provided and the feature is disabled.
-    pub async fn from_any(url: String) -> Vec<Self> {
-        let mut output = vec![];
-
-        if url.ends_with(".xml") {
-            output.push(SitemapDefinition::from_xml(url).await);
-        } else if url.ends_with(".tar.gz") {
-            ifcfg!("tar_gz_sitemaps", {
-                let mut sitemaps = SitemapDefinition::from_tar_gz(url).await;
-                output.append(&mut sitemaps);
-            });
-            ifncfg!("tar_gz_sitemaps", {
-                println!("Cannot process tarballed sitemaps, missing feature.");
-            })
-        }
-        output
-    }
-
-    /// Load a sitemap from an XML document pointed by an url and parse it.
-    pub async fn from_xml(url: String) -> Self {
-        println!("Reading XML sitemap from {url}");
-        SitemapDefinition::parser(surf::get(&url).recv_string().await.unwrap())
-    }
-
-    /// Load a sitemap from a compressed archive pointed by an url and parse it.
-    /// WARN: This function may fail with too large gzip files as everything is
-    /// loded on memory. 
+    /// Loads the sitemaps pointed by a URL. The format of the sitemap is
+    /// detected from its first bytes and its content type, see
+    /// `SitemapFormat`, so that URLs without extensions work too.
+    /// A gzip sitemap is decompressed while it's parsed, and a tarball gives
+    /// one sitemap per file. Tarballs need the `tar_gz_sitemaps` experimental
+    /// feature, an error is returned if it's disabled.
+    /// WARN: The body is read while it's parsed without being buffered, which
+    /// blocks the thread. This function must only run on the bot's thread.
+    pub async fn from_url(
+        url: String
+    ) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let mut res = surf::get(&url)
+            .header("User-Agent", CRAWLER_USER_AGENT.as_str())
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(Box::new(
+                StdError(format!("Unsuccesful response code {}", res.status()))
+            ));
+        }
+
+        let content_type = res.content_type().map(|m| m.essence().to_string());
+        let body = LimitedReader::new(BlockingReader(res.take_body()));
+
+        println!("Reading sitemap from {url}");
+        Self::from_reader(Box::new(body), content_type.as_deref(), &[])
+    }
+
+    /// Parses the sitemaps of a reader, see `from_url`. `layers` are the
+    /// formats of the files the reader was taken from.
+    /// INFO: A sitemap is decompressed at most once and unarchived at most
+    /// once, so that nested files can't be used to make the bot read forever.
+    fn from_reader(
+        reader: Box<dyn Read + '_>,
+        content_type: Option<&str>,
+        layers: &[SitemapFormat]
+    ) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let (format, reader) = SitemapFormat::detect(reader, content_type)?;
+
+        if layers.contains(&format) {
+            return Err(Box::new(StdError(
+                format!("Nested {format:?} sitemaps aren't supported")
+            )));
+        }
+
+        let layers = [layers, &[format]].concat();
+
+        match format {
+            SitemapFormat::Gzip => {
+                let decoder = MultiGzDecoder::new(BufReader::new(reader));
+
+                Self::from_reader(
+                    Box::new(LimitedReader::new(decoder)), None, &layers
+                )
+            },
+            SitemapFormat::Tar => Self::from_tar(reader, &layers),
+            SitemapFormat::Xml => Ok(vec![Self::parser(reader)?]),
+            SitemapFormat::Text => Ok(vec![Self::from_text(reader)?])
+        }
+    }
+
+    /// Parses the sitemaps of each file of a tarball.
     #[cfg(feature = "tar_gz_sitemaps")]
-    pub async fn from_tar_gz(url: String) -> Vec<Self> {
-        use tar::Archive;
-
-        let buf = surf::get(&url).await.unwrap().body_bytes().await.unwrap();
-        let mut archive = Archive::new(buf.as_slice());
-
-        println!("Reading compressed XML sitemap from {url}");
-        archive.entries()
-            .unwrap()
-            .map(|file| {
-                let mut file = file.unwrap();
-                let mut content = String::new();
-
-                file.read_to_string(&mut content).unwrap();
-                SitemapDefinition::parser(content)
-            })
-            .collect()
-    }
-
-    /// It's the function that actually parses the sitemap.
-    fn parser(data: String) -> Self {
-        let data = BufReader::new(data.as_bytes());
-        let xml_sitemap = EventReader::new(data);
-        let mut def = Self { outgoing_urls: vec![], is_index: false };
-        let mut nesting = vec![];
-
-        xml_sitemap.into_iter().for_each(|element| match element.unwrap() {
-            XmlEvent::StartElement { name, .. } => {
-                nesting.push(name);
+    fn from_tar(
+        reader: Box<dyn Read + '_>,
+        layers: &[SitemapFormat]
+    ) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let mut archive = tar::Archive::new(reader);
+        let mut sitemaps = vec![];
+
+        for file in archive.entries()? {
+            let file = file?;
+
+            if file.header().entry_type().is_file() {
+                sitemaps.append(
+                    &mut Self::from_reader(Box::new(file), None, layers)?
+                );
             }
-            XmlEvent::EndElement { name: _ } => {
-                nesting.pop();
+        }
+        Ok(sitemaps)
+    }
+
+    #[cfg(not(feature = "tar_gz_sitemaps"))]
+    fn from_tar(
+        _reader: Box<dyn Read + '_>,
+        _layers: &[SitemapFormat]
+    ) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        Err(Box::new(StdError(
+            "Cannot process tarballed sitemaps, missing feature.".into()
+        )))
+    }
+
+    /// Parses a text sitemap, which has a URL per line.
+    fn from_text(
+        reader: Box<dyn Read + '_>
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut entries = vec![];
+
+        for line in BufReader::new(reader).lines() {
+            if let Some(loc) = normalize_str(&line?) {
+                entries.push(SitemapEntry::new(loc));
+            }
+            if entries.len() >= MAX_SITEMAP_ENTRIES {
+                break;
             }
-            XmlEvent::Characters(data) => {
-                if data.ends_with(".gz") || data.ends_with(".xml") {
-                    def.is_index = true;
+        }
+        Ok(Self { kind: SitemapKind::UrlSet, entries })
+    }
+
+    /// It's the function that actually parses the sitemap. The root element
+    /// tells the kind of the sitemap, and each `<url>` or `<sitemap>` element
+    /// under it is an entry.
+    /// INFO: Only elements of the Sitemaps namespace, or without a namespace,
+    /// are read. Entries past `MAX_SITEMAP_ENTRIES` are left out.
+    fn parser(data: impl Read) -> Result<Self, Box<dyn std::error::Error>> {
+        let xml_sitemap = EventReader::new(BufReader::new(data));
+        let mut kind = None;
+        let mut entries: Vec<SitemapEntry> = vec![];
+        let mut entry: Option<SitemapEntry> = None;
+        // Local names of the open elements, `None` for foreign elements.
+        let mut nesting: Vec<Option<String>> = vec![];
+        let mut text = String::new();
+
+        for element in xml_sitemap {
+            match element? {
+                XmlEvent::StartElement { name, .. } => {
+                    let is_sitemap = name.namespace.as_deref()
+                        .is_none_or(|ns| SITEMAP_NAMESPACES.contains(&ns));
+                    let local = is_sitemap.then_some(name.local_name);
+
+                    match (nesting.len(), local.as_deref()) {
+                        (0, Some("urlset")) => kind = Some(SitemapKind::UrlSet),
+                        (0, Some("sitemapindex")) => {
+                            kind = Some(SitemapKind::Index)
+                        },
+                        (0, _) => return Err(Box::new(
+                            StdError("Not a sitemap".into())
+                        )),
+                        (1, Some("url" | "sitemap")) => {
+                            entry = Some(SitemapEntry::new(String::new()))
+                        },
+                        _ => ()
+                    }
+                    nesting.push(local);
+                    text.clear();
                 }
-                def.outgoing_urls.push(data);
+                XmlEvent::EndElement { .. } => {
+                    let local = nesting.pop().flatten();
+
+                    match (nesting.len(), local) {
+                        (1, Some(_)) => {
+                            if let Some(entry) = entry.take().and_then(|e| {
+                                Some(SitemapEntry {
+                                    loc: normalize_str(&e.loc)?,
+                                    ..e
+                                })
+                            }) {
+                                entries.push(entry);
+                            }
+                        },
+                        (2, Some(field)) if nesting[1].is_some() => {
+                            if let Some(entry) = entry.as_mut() {
+                                entry.set_field(&field, text.trim());
+                            }
+                        },
+                        _ => ()
+                    }
+                    if entries.len() >= MAX_SITEMAP_ENTRIES {
+                        break;
+                    }
+                }
+                XmlEvent::Characters(data) | XmlEvent::CData(data) => {
+                    text.push_str(&data);
+                }
+                _ => {}
             }
-            _ => {}
+        }
+
+        Ok(Self {
+            kind: kind.ok_or(StdError("Empty sitemap".into()).to_boxed())?,
+            entries
+        })
+    }
+
+    /// Removes the entries on another host than the sitemap at `url`, as the
+    /// Sitemaps protocol requires, and returns how many were removed. A
+    /// sitemap can't make the crawler visit other sites this way.
+    fn retain_host(&mut self, url: &str) -> usize {
+        let host = Url::parse(url).ok()
+            .and_then(|u| u.host_str().map(String::from));
+        let count = self.entries.len();
+
+        self.entries.retain(|e| {
+            host.is_some() &&
+                Url::parse(&e.loc).ok()
+                    .is_some_and(|u| u.host_str() == host.as_deref())
+        });
+        count - self.entries.len()
+    }
+
+    /// Returns the entries in the order they should be crawled: by decreasing
+    /// crawl priority, see `SitemapEntry::crawl_priority`, then most recently
+    /// modified first.
+    pub fn entries_by_priority(&self) -> Vec<&SitemapEntry> {
+        let mut entries = self.entries.iter().collect::<Vec<&SitemapEntry>>();
+
+        entries.sort_by(|a, b| {
+            b.crawl_priority().total_cmp(&a.crawl_priority())
+                .then_with(|| b.lastmod.cmp(&a.lastmod))
         });
+        entries
+    }
+}
 
-        def
+/// Reads a sitemap, queues the URLs of a `<urlset>` and saves the sitemaps of
+/// a `<sitemapindex>`. Returns the kind of the sitemap and its number of
+/// entries.
+/// INFO: Entries on another host than the sitemap are skipped, see
+/// `SitemapDefinition::retain_host`.
+async fn read_sitemap(
+    url: &str
+) -> Result<(Option<SitemapKind>, usize), Box<dyn std::error::Error>> {
+    let mut sitemaps = SitemapDefinition::from_url(url.to_string()).await?;
+    let mut url_count = 0;
+
+    for sitemap in &mut sitemaps {
+        let skipped = sitemap.retain_host(url);
+
+        if skipped > 0 {
+            println!("[SITEMAPS] Skipped {skipped} URLs on other hosts: {url}");
+        }
+        url_count += sitemap.entries.len();
+        match sitemap.kind {
+            SitemapKind::UrlSet => {
+                QUEUE_BOT.queue_url(sitemap.entries_by_priority()
+                    .into_iter()
+                    .map(|e| FrontierEntry::sitemap(
+                        e.loc.clone(), e.crawl_priority()
+                    ))
+                    .collect())
+            },
+            SitemapKind::Index => for entry in &sitemap.entries {
+                db::sitemaps::save_sitemap(
+                    &entry.loc, Some(url), entry.lastmod
+                )?;
+            }
+        }
     }
+    Ok((sitemaps.first().map(|s| s.kind), url_count))
 }
 
-/// The `SitemapBot` will manage sitemaps to visit by creating a queue of every
-/// sitemap that shuold be visited on a separate thread. This bot will send 
-/// URLs to visit to the `QueueBot`.
+/// The `SitemapBot` reads the sitemaps saved on the database on a separate
+/// thread, each one when it's due, see `db::sitemaps`. This bot will send URLs
+/// to visit to the `QueueBot`.
+/// Sitemaps are read again every `SITEMAP_REFRESH` seconds, and sooner when
+/// their sitemap index tells they were modified.
 pub struct SitemapBot {
-    queue: Arc<Mutex<Vec<String>>>
+    /// Set when a sitemap is queued, to wake the bot up.
+    wakeup: Arc<(Mutex<bool>, Condvar)>
 }
 
-unsafe impl Send for SitemapBot {}
-unsafe impl Sync for SitemapBot {}
 impl SitemapBot {
     pub fn init() -> Self {
         Self { 
-            queue: Arc::new(Mutex::new(vec![])) 
+            wakeup: Arc::new((Mutex::new(false), Condvar::new()))
         }
     }
 
+    /// Saves a sitemap to read, a new sitemap is read right away. A known
+    /// sitemap is read on its schedule.
     pub fn queue_sitemap(&self, sitemap: String) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.push(sitemap);
+        if let Err(err) = db::sitemaps::save_sitemap(&sitemap, None, None) {
+            println!("[SITEMAPS] Failed to save {sitemap}: {err}");
+            return;
+        }
+
+        let (queued, condvar) = &*self.wakeup;
+
+        *queued.lock().unwrap() = true;
+        condvar.notify_one();
     }
 
-    /// Starts parallel indexing.
+    /// Starts reading sitemaps. The bot sleeps until the next sitemap is due,
+    /// or until a sitemap is queued. If the next sitemap can't be known, it
+    /// sleeps for `SITEMAP_RETRY_DELAY` at most.
     pub fn thread_bot(&self) {
-        let queue_clone = self.queue.clone();
+        let wakeup_clone = self.wakeup.clone();
 
         thread::spawn(move || {
             let rt = Runtime::new().unwrap();
             let mut tg = { ifcfg!("debug", TimingGatherer::init()) };
 
             loop {
-                let mut queue: Vec<String> = vec![];
+                // When the database fails, the bot tries again after a delay
+                // instead of sleeping until a sitemap is queued.
+                let (sitemap, retry) = match db::sitemaps::get_next_sitemap() {
+                    Ok(sitemap) => (sitemap, None),
+                    Err(err) => {
+                        println!("[SITEMAPS] Failed to read sitemaps: {err}");
+                        (None, Some(SITEMAP_RETRY_DELAY))
+                    }
+                };
+                let now = Utc::now();
 
-                ifcfg!("debug", tg.start_gathering());
-                // We free the shared queue and drop it to make it available as
-                // soon as possible.
+                if let Some(sitemap) = sitemap.as_ref()
+                    .filter(|s| s.next_fetch_at <= now)
                 {
-                    let mut guard = queue_clone.lock().unwrap();
-                    std::mem::swap(&mut queue, &mut *guard);
-                }
-                for url in &queue {
-                    rt.block_on(async {
-                        let sitemap_data = 
-                            SitemapDefinition::from_any(url.into()).await;
-                        for sitemap in sitemap_data {
-                            if !sitemap.is_index {
-                                QUEUE_BOT.queue_url(sitemap.outgoing_urls);
-                            } else {
-                                for sitemap_url in sitemap.outgoing_urls {
-                                    SITEMAP_BOT.queue_sitemap(sitemap_url);
-                                }
-                            }
+                    ifcfg!("debug", tg.start_gathering());
+
+                    let result = rt.block_on(read_sitemap(&sitemap.url));
+                    let saved = match result {
+                        Ok((kind, url_count)) => db::sitemaps::update_fetch(
+                            &sitemap.url,
+                            Ok((kind.map(|k| k.as_str()), url_count)),
+                            *SITEMAP_REFRESH
+                        ),
+                        Err(err) => {
+                            println!("[SITEMAPS] {}: {err}", sitemap.url);
+                            db::sitemaps::update_fetch(
+                                &sitemap.url,
+                                Err(err.to_string()),
+                                SITEMAP_RETRY_DELAY.min(*SITEMAP_REFRESH)
+                            )
                         }
-                    });
+                    };
+
+                    if let Err(err) = saved {
+                        println!(
+                            "[SITEMAPS] Failed to save {}: {err}", sitemap.url
+                        );
+                    }
                     ifcfg!("debug", tg.action_done());
                     ifcfg!("debug", {
                         if tg.actions_done % 10 == 0 {
                             tg.log_gathered_data();
                         }
                     });
+                    continue;
                 }
+
+                // No sitemap is due, the bot waits for the next one or for a
+                // new one to be queued.
+                let (queued, condvar) = &*wakeup_clone;
+                let mut guard = queued.lock().unwrap();
+                let wait = sitemap.map(|s| {
+                    (s.next_fetch_at - now).to_std().unwrap_or_default()
+                }).or(retry);
+
+                if !*guard {
+                    guard = match wait {
+                        Some(wait) => condvar.wait_timeout(guard, wait)
+                            .unwrap().0,
+                        None => condvar.wait(guard).unwrap()
+                    };
+                }
+                *guard = false;
             }
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sitemap(content: &str) -> SitemapDefinition {
+        SitemapDefinition::parser(content.as_bytes()).unwrap()
+    }
+
+    fn date(value: &str) -> Option<DateTime<Utc>> {
+        Some(DateTime::parse_from_rfc3339(value).unwrap().to_utc())
+    }
+
+    #[test]
+    fn urlsets_list_pages() {
+        let sitemap = sitemap(r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">
+                <url>
+                    <loc>https://example.com/page</loc>
+                    <lastmod>2024-05-01T12:30+02:00</lastmod>
+                    <changefreq>Daily</changefreq>
+                    <priority>0.8</priority>
+                    <image:image>
+                        <image:loc>https://example.com/image.png</image:loc>
+                    </image:image>
+                </url>
+                <url>
+                    <loc> https://example.com/other </loc>
+                    <changefreq>sometimes</changefreq>
+                    <priority>2</priority>
+                </url>
+                <url><loc>ftp://example.com/file</loc></url>
+            </urlset>
+        "#);
+
+        assert_eq!(sitemap.kind, SitemapKind::UrlSet);
+        assert_eq!(sitemap.entries, vec![
+            SitemapEntry {
+                loc: "https://example.com/page".into(),
+                lastmod: date("2024-05-01T10:30:00Z"),
+                changefreq: Some(ChangeFreq::Daily),
+                priority: Some(0.8)
+            },
+            SitemapEntry::new("https://example.com/other".into())
+        ]);
+    }
+
+    #[test]
+    fn indexes_list_sitemaps() {
+        let sitemap = sitemap(r#"
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <sitemap>
+                    <loc>https://example.com/1.xml.gz</loc>
+                    <lastmod>2024-05-01</lastmod>
+                </sitemap>
+                <sitemap>
+                    <loc>https://example.com/2.xml.gz</loc>
+                    <lastmod>2024-05-01T12:30:00Z</lastmod>
+                    <priority>0.3</priority>
+                </sitemap>
+            </sitemapindex>
+        "#);
+
+        assert_eq!(sitemap.kind, SitemapKind::Index);
+        assert_eq!(sitemap.entries, vec![
+            SitemapEntry {
+                lastmod: date("2024-05-01T00:00:00Z"),
+                ..SitemapEntry::new("https://example.com/1.xml.gz".into())
+            },
+            SitemapEntry {
+                lastmod: date("2024-05-01T12:30:00Z"),
+                priority: Some(0.3),
+                ..SitemapEntry::new("https://example.com/2.xml.gz".into())
+            }
+        ]);
+    }
+
+    #[test]
+    fn entries_on_other_hosts_are_skipped() {
+        let mut sitemap = sitemap(r#"
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url><loc>https://example.com/page</loc></url>
+                <url><loc>https://EXAMPLE.com:443/other</loc></url>
+                <url><loc>http://example.com/insecure</loc></url>
+                <url><loc>https://www.example.com/page</loc></url>
+                <url><loc>https://spam.test/page</loc></url>
+            </urlset>
+        "#);
+
+        assert_eq!(sitemap.retain_host("https://example.com/sitemap.xml"), 2);
+        assert_eq!(
+            sitemap.entries.iter().map(|e| e.loc.as_str()).collect::<Vec<_>>(),
+            [
+                "https://example.com/page",
+                "https://example.com/other",
+                "http://example.com/insecure"
+            ]
+        );
+        assert_eq!(sitemap.retain_host("not a url"), 3);
+        assert!(sitemap.entries.is_empty());
+    }
+
+    #[test]
+    fn foreign_documents_arent_sitemaps() {
+        let document = r#"<html xmlns="http://www.w3.org/1999/xhtml"></html>"#;
+
+        assert!(SitemapDefinition::parser(document.as_bytes()).is_err());
+        assert!(SitemapDefinition::parser("".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn entries_are_capped() {
+        let urls = (0..MAX_SITEMAP_ENTRIES + 10)
+            .map(|i| format!("<url><loc>https://example.com/{i}</loc></url>"))
+            .collect::<String>();
+        let sitemap = sitemap(&format!("<urlset>{urls}</urlset>"));
+
+        assert_eq!(sitemap.entries.len(), MAX_SITEMAP_ENTRIES);
+        assert_eq!(
+            sitemap.entries.last().unwrap().loc,
+            format!("https://example.com/{}", MAX_SITEMAP_ENTRIES - 1)
+        );
+
+        let urls = (0..MAX_SITEMAP_ENTRIES + 10)
+            .map(|i| format!("https://example.com/{i}\n"))
+            .collect::<String>();
+        let sitemap = SitemapDefinition::from_text(
+            Box::new(urls.as_bytes())
+        ).unwrap();
+
+        assert_eq!(sitemap.entries.len(), MAX_SITEMAP_ENTRIES);
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(
+            vec![], flate2::Compression::default()
+        );
+
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn from_bytes(
+        data: Vec<u8>
+    ) -> Result<Vec<SitemapDefinition>, Box<dyn std::error::Error>> {
+        SitemapDefinition::from_reader(Box::new(Cursor::new(data)), None, &[])
+    }
+
+    #[test]
+    fn gzip_sitemaps_are_decompressed_once() {
+        let text = b"https://example.com/page\n";
+        let sitemaps = from_bytes(gzip(text)).unwrap();
+
+        assert_eq!(sitemaps.len(), 1);
+        assert_eq!(sitemaps[0].entries.len(), 1);
+        assert!(from_bytes(gzip(&gzip(text))).is_err());
+    }
+
+    #[cfg(feature = "tar_gz_sitemaps")]
+    #[test]
+    fn tarballs_are_unarchived_once() {
+        fn tar(files: &[&[u8]]) -> Vec<u8> {
+            let mut builder = tar::Builder::new(vec![]);
+
+            for (i, data) in files.iter().enumerate() {
+                let mut header = tar::Header::new_gnu();
+
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                builder.append_data(&mut header, format!("{i}.xml"), *data)
+                    .unwrap();
+            }
+            builder.into_inner().unwrap()
+        }
+
+        let urlset = b"<urlset><url><loc>https://example.com/</loc></url>\
+            </urlset>";
+        let tarball = tar(&[urlset, urlset]);
+
+        assert_eq!(from_bytes(gzip(&tarball)).unwrap().len(), 2);
+        assert_eq!(from_bytes(tar(&[&gzip(urlset)])).unwrap().len(), 1);
+        assert!(from_bytes(tar(&[&tarball])).is_err());
+        assert!(from_bytes(gzip(&tar(&[&gzip(urlset)]))).is_err());
+    }
+
+    #[test]
+    fn changefreq_raises_the_crawl_priority() {
+        let sitemap = sitemap("
+            <urlset>
+                <url><loc>https://example.com/never</loc>
+                    <changefreq>never</changefreq></url>
+                <url><loc>https://example.com/default</loc></url>
+                <url><loc>https://example.com/hourly</loc>
+                    <changefreq>hourly</changefreq></url>
+                <url><loc>https://example.com/important</loc>
+                    <changefreq>never</changefreq>
+                    <priority>1.0</priority></url>
+            </urlset>
+        ");
+        let order = sitemap.entries_by_priority()
+            .into_iter()
+            .map(|e| e.loc.trim_start_matches("https://example.com/"))
+            .collect::<Vec<&str>>();
+
+        assert_eq!(order, vec!["important", "hourly", "default", "never"]);
+        assert_eq!(sitemap.entries[1].crawl_priority(), DEFAULT_PRIORITY);
+    }
+}
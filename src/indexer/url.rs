@@ -1,24 +1,41 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::thread;
 use tokio::runtime::Runtime;
 use scraper::{ElementRef, Html, Selector};
 use url::Url;
-use crate::data_pool::DataPool;
+use crate::analysis::Analyzer;
+use crate::db::postings::{
+    FieldFrequencies,
+    FIELD_BODY,
+    FIELD_DESCRIPTION,
+    FIELD_HEADING,
+    FIELD_TITLE,
+    MAX_POSITIONS
+};
 use crate::{db, INDEXED_URLS_NB};
+use crate::db::frontier::{url_host, FrontierEntry};
 use crate::debug::gatherers::TimingGatherer;
 use crate::error::StdError;
 use crate::ifcfg;
-use crate::sanitize::sanitize_string;
 use crate::QUEUE_BOT;
+use super::alternates::{get_alternates, own_hreflang};
+use super::canonical::{get_canonical, normalize_str, resolve_url};
+use super::directives::{is_nofollow_link, RobotsDirectives};
+use super::feeds::{discover_feeds, DEFAULT_POLL_INTERVAL};
+use super::fetch::{fetch_page, Fetched, Validators};
 use super::localization::{auto_choose_localization, get_localization};
+use super::robots::{
+    RobotsDefinition,
+    CRAWLER_USER_AGENT,
+    DEFAULT_CRAWL_DELAY
+};
 
 /// Extract all texts from a root element.
 pub fn get_all_texts(from: ElementRef) -> Vec<String> {
     from.children()
-        .into_iter()
-        .map(|c| {
+        .flat_map(|c| {
             if c.value().is_text() {
                 vec![c.value().as_text().unwrap().to_string()]
             } else {
@@ -31,55 +48,97 @@ pub fn get_all_texts(from: ElementRef) -> Vec<String> {
                 }).collect()
             }
         })
-        .flatten()
-        .filter(|f| f.len() > 0)
+        .filter(|f| !f.is_empty())
         .collect::<Vec<_>>()
 }
 
-/// Indexing data, before it can be stored on the database.
+/// Extract all texts from the elements matching a selector.
+pub fn get_selector_texts(dom: &Html, selector: &Selector) -> Vec<String> {
+    dom.select(selector)
+        .flat_map(get_all_texts)
+        .collect()
+}
+
+/// Maximum number of bytes of body text kept for a page, it's enough to find
+/// snippets without storing whole pages.
+const MAX_CONTENT_LENGTH: usize = 16384;
+
+/// Delay before crawling a host again when its robots file is unreachable.
+const ROBOTS_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Maximum time the queue bot waits for new URLs when it has none to crawl.
+const QUEUE_IDLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Gap between the positions of words of two different parts of a page, it
+/// avoids matching phrases across those parts.
+const POSITION_GAP: u32 = 16;
+
+/// Indexing data, before it can be stored on the database. Each word is linked
+/// to its frequency in each field of the page and to its positions, and the
+/// length of the page is the number of words found on it. The body text of the
+/// page is also kept.
+/// Words are analyzed with the analyzer of the language of the page, stopwords
+/// aren't indexed but still take a position.
 #[derive(Debug)]
 pub struct IndexData {
-    words: HashMap<String, usize>
+    analyzer: Arc<Analyzer>,
+    words: HashMap<String, (FieldFrequencies, Vec<u32>)>,
+    length: usize,
+    position: u32,
+    content: String
 }
 
 impl IndexData {
-    pub fn new() -> Self {
-        Self { words: HashMap::new() }
+    pub fn new(analyzer: Arc<Analyzer>) -> Self {
+        Self {
+            analyzer,
+            words: HashMap::new(),
+            length: 0,
+            position: 0,
+            content: String::new()
+        }
     }
 
-    /// Increase a word score from a string vec and a multiplier.
-    pub fn incr_score(&mut self, lines: Vec<String>, rate: usize) {
+    /// Increase words frequencies in a field from a string vec.
+    pub fn incr_frequency(&mut self, lines: Vec<String>, field: u8) {
+        self.position += POSITION_GAP;
         lines.iter().for_each(|line| {
-            sanitize_string(line).iter().for_each(|w| {
-                match self.words.get(w) {
-                    Some(count) => { 
-                        self.words.insert(w.into(), count + rate); 
-                    }
-                    None => { 
-                        self.words.insert(w.into(), rate); 
+            for word in self.analyzer.analyze(line) {
+                if let Some(w) = word {
+                    let (tf, positions) = self.words.entry(w).or_default();
+
+                    tf.incr(field);
+                    if positions.len() < MAX_POSITIONS {
+                        positions.push(self.position);
                     }
+                    self.length += 1;
                 }
-            });
+                self.position += 1;
+            }
+            if field == FIELD_BODY {
+                self.push_content(line);
+            }
         })
     }
 
-    /// Increase a score from a selector.
-    pub fn incr_score_selector(
-        &mut self, dom: &Html, selector: Selector, rate: usize
-    ) {
-        let content = dom.select(&selector)
-            .into_iter()
-            .map(|c| get_all_texts(c))
-            .flatten()
-            .collect::<Vec<_>>();
-        self.incr_score(content, rate);
+    /// Adds a line to the body text, whitespaces are collapsed.
+    fn push_content(&mut self, line: &str) {
+        for part in line.split_whitespace() {
+            if self.content.len() + part.len() + 1 > MAX_CONTENT_LENGTH {
+                return;
+            }
+            if !self.content.is_empty() {
+                self.content.push(' ');
+            }
+            self.content.push_str(part);
+        }
     }
 
     /// Get the Type-Token Ratio to determine the quality of the page and add it
     /// to the website quality attribute.
     pub fn get_ttr(&self) -> f64 {
         let word_set: HashSet<String> = HashSet::from_iter(
-            self.words.keys().into_iter().map(|w| w.to_string())
+            self.words.keys().map(|w| w.to_string())
         );
         let word_count = self.words.keys().len();
         let ttr = word_set.len() as f64 / word_count as f64;
@@ -88,44 +147,239 @@ impl IndexData {
     }
 }
 
+/// Whether the crawler may fetch a URL, see `check_crawlable`.
+pub enum Crawl {
+    /// The URL may be fetched, the next request to its host must wait for the
+    /// given delay.
+    Allowed(Duration),
+    /// The URL must not be fetched, for the given reason.
+    Skipped(String),
+    /// The URL can't be fetched for now, its host must be retried after the
+    /// given delay.
+    Postponed(String, Duration)
+}
+
+/// Checks if the crawler may fetch a URL according to the robots rules of its
+/// domain, which are fetched on the first contact and once expired.
+/// When the URL may be fetched, the delay before the next request to its host
+/// is the `Crawl-delay` of the robots rules or `DEFAULT_CRAWL_DELAY`.
+/// INFO: A URL is postponed when its robots rules can't be fetched, nothing
+/// may be crawled on its domain until then.
+pub async fn check_crawlable(url: &str) -> Crawl {
+    let Ok(parsed_url) = Url::parse(url) else {
+        return Crawl::Skipped("Invalid URL".into());
+    };
+
+    if parsed_url.domain().is_none() {
+        return Crawl::Skipped("No domain name".into());
+    }
+    match RobotsDefinition::for_url(&parsed_url).await {
+        Ok(robots) if !robots.is_allowed_url(&parsed_url) => Crawl::Skipped(
+            format!("Disallowed by robots.txt for {}", *CRAWLER_USER_AGENT)
+        ),
+        Ok(robots) => Crawl::Allowed(
+            robots.crawl_delay(&CRAWLER_USER_AGENT)
+                .unwrap_or(*DEFAULT_CRAWL_DELAY)
+        ),
+        Err(err) => Crawl::Postponed(
+            format!("Failed to fetch robots.txt: {err}"), ROBOTS_RETRY_DELAY
+        )
+    }
+}
+
+/// Result of the indexing of a URL that was fetched.
+pub enum IndexOutcome {
+    /// The page was stored, `nofollow` tells if its links were left out.
+    Indexed { nofollow: bool },
+    /// The page must not be stored, for the given reason. It was removed if it
+    /// was indexed before.
+    Excluded(String),
+    /// The page declares another canonical URL, it's only indexed under the
+    /// given canonical URL. It was removed if it was indexed before.
+    Duplicate(String),
+    /// The page didn't change since it was indexed, it was kept as-is.
+    Unchanged
+}
+
+/// Queues the pages a page links to, one level deeper than the page. Links with
+/// `rel="nofollow"` are left out.
+fn discover_links(dom: &Html, url: &str, depth: u32) {
+    let a_selector = Selector::parse("a[href]").unwrap();
+    let new_links = dom.select(&a_selector)
+        .filter(|a| !is_nofollow_link(a))
+        .filter_map(|a| QueueBot::ensure_url_format(
+            url.to_string(), a.attr("href").unwrap().to_string()
+        ).ok())
+        .map(|link| FrontierEntry::link(link, depth + 1))
+        .collect::<Vec<FrontierEntry>>();
+
+    println!("Found automatically {} links to index.", new_links.len());
+    QUEUE_BOT.queue_url(new_links);
+}
+
 /// Indexes websites and store results in the database, check the documentation
 /// at `Indexing` to understand how it proceeds.
-pub async fn index_url(url: String) -> Result<(), Box<dyn std::error::Error>> {
-    let mut scoreboard = IndexData::new();
-    let mut res = surf::get(url.clone()).await?;
+/// Pages are not stored if their directives forbid it, and their links and
+/// feeds aren't followed if their directives forbid it too, see
+/// `RobotsDirectives`.
+/// Redirects are followed, see `fetch_page`, and pages are stored under the
+/// URL they were finally fetched at. Pages that didn't change since they were
+/// indexed are kept as-is, see `Validators`.
+/// The depth of the page is the number of links followed to find it, its links
+/// are queued one level deeper.
+pub async fn index_url(
+    url: String,
+    depth: u32
+) -> Result<IndexOutcome, Box<dyn std::error::Error>> {
+    let Fetched { url, response: mut res, redirects } = fetch_page(&url).await?;
+
+    // URLs permanently redirecting to a page aren't indexed anymore, the page
+    // is. Pages of temporarily redirected URLs are kept, those come back once
+    // the redirect ends.
+    db::redirects::record(&redirects)?;
+    db::redirects::forget(&url)?;
+    for redirect in &redirects {
+        println!(
+            "[REDIRECT] {} -> {} ({}, {})",
+            redirect.from,
+            redirect.to,
+            redirect.status,
+            if redirect.is_permanent() { "permanent" } else { "temporary" }
+        );
+        if redirect.is_permanent() {
+            db::alternates::delete_alternates(&redirect.from)?;
+            db::sites::delete_url_record(&redirect.from)?;
+        }
+    }
+
+    // The page didn't change since it was indexed, see `Validators`.
+    if res.status() == 304 {
+        return Ok(IndexOutcome::Unchanged);
+    }
+
     let page = res.body_string().await?;
+    let content_language = res.header("Content-Language")
+        .map(|h| h.as_str().to_string());
+    let link = res.header("Link").map(|h| {
+        h.iter().map(|v| v.as_str()).collect::<Vec<&str>>().join(", ")
+    });
+    let robots_tags = res.header("X-Robots-Tag")
+        .map(|h| h.iter().map(|v| v.as_str().to_string()).collect())
+        .unwrap_or(vec![]);
     let dom = Html::parse_fragment(&page);
     let parsed_url = Url::parse(&url).unwrap();
 
     let title_selector = Selector::parse("title").unwrap();
     let desc_selector = Selector::parse("meta[name='description']").unwrap();
     let p_selector = Selector::parse("p, span").unwrap();
-    let a_selector = Selector::parse("a").unwrap();
-    let h1_selector = Selector::parse("h1").unwrap();
-    let h2_selector = Selector::parse("h2").unwrap();
-    let h3_selector = Selector::parse("h3").unwrap();
-    let h4_selector = Selector::parse("h4").unwrap();
-    let h5_selector = Selector::parse("h5").unwrap();
-
-    let mut final_title = String::from("unnamed");
-    let mut final_desc = String::from("No description.");
+    let h_selector = Selector::parse("h1, h2, h3, h4, h5").unwrap();
 
     if !res.status().is_success() {
-        return StdError("Unsuccesful response code".into()).to_boxed_err()
+        return Err(StdError("Unsuccesful response code".into()).to_boxed())
+    }
+
+    // Servers without validators may still send the same content again.
+    let validators = Validators::from_response(&res, &page);
+
+    if db::sites::get_validators(&url)?
+        .is_some_and(|v| v.content_hash == validators.content_hash)
+    {
+        db::sites::update_site_validators(&url, &validators)?;
+        return Ok(IndexOutcome::Unchanged);
+    }
+
+    // Links of the page can be followed even if it must not be indexed.
+    let directives = RobotsDirectives::from_page(&dom, &robots_tags);
+    let alternates = get_alternates(&dom, link.as_deref(), &parsed_url);
+
+    if !directives.nofollow {
+        if cfg!(feature = "auto_queue") {
+            discover_links(&dom, &url, depth);
+        }
+        QUEUE_BOT.queue_url(
+            db::alternates::filter_unindexed(&alternates)?
+                .into_iter()
+                .map(|alternate| FrontierEntry::link(alternate, depth))
+                .collect()
+        );
+        for feed in discover_feeds(&dom, &parsed_url) {
+            if db::feeds::save_feed(&feed, &url, DEFAULT_POLL_INTERVAL)? {
+                println!("[FEEDS] Discovered {feed} on {url}");
+            }
+        }
+    }
+    if directives.noindex {
+        let reason = directives.describe().unwrap();
+
+        db::alternates::delete_alternates(&url)?;
+        if db::sites::delete_url_record(&url)? {
+            println!("[DIRECTIVES] Removed previously indexed {url}");
+        }
+        db::skipped_urls::record(&url, &format!("Excluded by {reason}"))?;
+        return Ok(IndexOutcome::Excluded(reason));
     }
+
+    // A page declaring another canonical URL is a duplicate, only the page at
+    // the canonical URL is indexed. When two pages declare each other, the
+    // last one crawled is indexed.
+    let mut canonical = get_canonical(&dom, link.as_deref(), &parsed_url)
+        .unwrap_or(url.clone());
+
+    if db::canonicals::get_canonical_of(&canonical)?.as_ref() == Some(&url) {
+        canonical = url.clone();
+    }
+
+    db::canonicals::save_canonical(&url, &canonical)?;
+    if canonical != url {
+        db::alternates::delete_alternates(&url)?;
+        if db::sites::delete_url_record(&url)? {
+            println!("[CANONICAL] Removed previously indexed {url}");
+        }
+        db::skipped_urls::record(&url, &format!("Duplicate of {canonical}"))?;
+        if !db::sites::is_url_indexed(&canonical)? {
+            QUEUE_BOT.queue_url(
+                vec![FrontierEntry::link(canonical.clone(), depth)]
+            );
+        }
+        return Ok(IndexOutcome::Duplicate(canonical));
+    }
+
     // INFO: To get the first element out of a DOM selector, you somehow have to
     // call `next`.
-    if let Some(title) = dom.select(&title_selector).next() {
-        let title_content = title.first_child().unwrap()
+    let title = dom.select(&title_selector).next().map(|title| {
+        title.first_child().unwrap()
             .value().as_text().unwrap()
-            .to_string();
-        scoreboard.incr_score(vec![title_content.clone()], 20);
-        final_title = title_content;
+            .to_string()
+    });
+    let desc = dom.select(&desc_selector).next()
+        .map(|desc| desc.attr("content").unwrap().to_string());
+    let body = get_selector_texts(&dom, &p_selector);
+    let headings = get_selector_texts(&dom, &h_selector);
+
+    // The language of the page is needed to analyze its words, it's detected
+    // from its text when not declared.
+    let text = body.iter()
+        .chain(&headings)
+        .chain(title.iter())
+        .chain(desc.iter())
+        .map(|t| t.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let localization = get_localization(
+        &dom,
+        content_language.as_deref(),
+        own_hreflang(parsed_url.as_str(), &alternates),
+        &text
+    );
+    let mut scoreboard = IndexData::new(
+        Analyzer::for_language(&localization.0)
+    );
+
+    if let Some(title) = &title {
+        scoreboard.incr_frequency(vec![title.clone()], FIELD_TITLE);
     }
-    if let Some(desc) = dom.select(&desc_selector).next() {
-        let desc_content = desc.attr("content").unwrap().to_string();
-        scoreboard.incr_score(vec![desc_content.clone()], 8);
-        final_desc = desc_content;
+    if let Some(desc) = &desc {
+        scoreboard.incr_frequency(vec![desc.clone()], FIELD_DESCRIPTION);
     }
 
     // We create a record of the current domain to avoid any error related to
@@ -139,136 +393,211 @@ pub async fn index_url(url: String) -> Result<(), Box<dyn std::error::Error>> {
     )?;
 
     // We create a record of the current url on the database for later linking.
-    db::sites::new_url_record(url.clone(), final_title, final_desc)?;
-    scoreboard.incr_score_selector(&dom, p_selector, 1);
-    scoreboard.incr_score_selector(&dom, h1_selector, 15);
-    scoreboard.incr_score_selector(&dom, h2_selector, 10);
-    scoreboard.incr_score_selector(&dom, h3_selector, 7);
-    scoreboard.incr_score_selector(&dom, h4_selector, 5);
-    scoreboard.incr_score_selector(&dom, h5_selector, 3);
-
-    // We find other URLs we could index.
-    if cfg!(feature = "auto_queue") {
-        let new_links = dom.select(&a_selector)
-            .into_iter()
-            .map(|a| a.attr("href"))
-            .filter(|r| r.is_some())
-            .map(|a| QueueBot::ensure_url_format(
-                url.clone(), a.unwrap().to_string()
-            ))
-            .filter(|r| r.is_ok())
-            .map(|a| a.unwrap())
-            .collect::<Vec<String>>();
-        println!("Found automatically {} links to index.", new_links.len());
-        QUEUE_BOT.queue_url(new_links);
-    }
+    db::sites::new_url_record(
+        url.clone(),
+        title.unwrap_or("unnamed".into()),
+        desc.unwrap_or("No description.".into())
+    )?;
+    db::sites::update_site_validators(&url, &validators)?;
+    scoreboard.incr_frequency(body, FIELD_BODY);
+    scoreboard.incr_frequency(headings, FIELD_HEADING);
 
-    // For each word, we link the current website to the word's table with it's
-    // score with this word.
-    scoreboard.words
-        .keys()
-        .for_each(|word| {
-            let score = scoreboard.words.get(word).unwrap();
-            let _ = db::_word::save_word_score(
-                url.clone(), 
-                word.clone(), 
-                *score
-            );
-        });
+    // Versions of the page in other languages are part of its cluster, those
+    // not indexed yet were queued.
+    db::alternates::save_alternates(&url, &alternates)?;
+
+    // Every word is linked to the current website through the postings store
+    // with it's frequencies for this word. The page length is saved to allow
+    // BM25 ranking.
+    db::postings::save_site_postings(&url, &scoreboard.words)?;
+    db::sites::update_site_length(&url, scoreboard.length)?;
+    db::sites::update_site_content(&url, &scoreboard.content)?;
 
     // The TTR and localization are saved alongside the site's data to determine the 
     // site's content quality.
     db::sites::update_site_ttr(&url, scoreboard.get_ttr())?;
     db::sites::update_site_loc(
         &url, 
-        auto_choose_localization(get_localization(dom), scoreboard.get_ttr())
+        auto_choose_localization(localization, scoreboard.get_ttr())
     )?; 
 
-    Ok(())
+    Ok(IndexOutcome::Indexed { nofollow: directives.nofollow })
 }
 
 pub struct QueueBot {
-    data_pool: Arc<Mutex<DataPool<String>>>,
+    /// Time before which no URL of a host is crawled, see `delay_host`.
+    host_delays: Arc<Mutex<HashMap<String, Instant>>>,
     pub is_paused: Arc<Mutex<bool>>
 }
 
+/// Prevents URLs of a host from being crawled before a delay.
+fn delay_host(
+    host_delays: &Mutex<HashMap<String, Instant>>,
+    host: String,
+    delay: Duration
+) {
+    let mut host_delays = host_delays.lock().unwrap();
+    let now = Instant::now();
+
+    host_delays.retain(|_, at| *at > now);
+    host_delays.insert(host, now + delay);
+}
+
 unsafe impl Send for QueueBot {}
 unsafe impl Sync for QueueBot {}
 impl QueueBot {
     pub fn init() -> Self {
         Self { 
-            data_pool: Arc::new(Mutex::new(DataPool::init())),
+            host_delays: Arc::new(Mutex::new(HashMap::new())),
             is_paused: Arc::new(Mutex::new(false))
         }
     }
 
-    pub fn get_remaining_urls(&self) -> Vec<String> {
-        self.data_pool.lock().unwrap().get_content()
+    /// Returns the number of URLs waiting to be crawled.
+    pub fn queue_length(&self) -> usize {
+        db::frontier::count_pending().unwrap_or(0)
     }
 
     /// This function MUST be called when auto-queuing to ensure only correcly
     /// formatted URLs are submitted.
     /// The source parameter is used to ensure that relative URLs gets their 
-    /// absolute definition before being submitted to the queue.
+    /// absolute definition before being submitted to the queue. URLs are
+    /// normalized, see `normalize_url`, and only HTTP URLs are accepted.
     pub fn ensure_url_format(
         source: String, url: String
     ) -> Result<String, Box<dyn std::error::Error>> {
         let source_url = Url::parse(&source)?;
-        let mut url = Url::parse(&url)?;
-
-        if url.domain().is_none() {
-            let _ = url.set_host(Some(&source_url.host().unwrap().to_string()));
-            let _ = url.set_scheme(source_url.scheme());
-        }
 
-        Ok(url_escape::decode(&url.to_string()).to_string())
+        resolve_url(&source_url, &url).ok_or(
+            StdError(format!("Not an HTTP URL: {url}")).to_boxed().into()
+        )
     }
 
-    pub fn queue_url(&self, urls: Vec<String>) {
-        self.data_pool.lock().unwrap().add_batch(urls);
+    /// Queues URLs to crawl, those are normalized first and URLs that were
+    /// permanently redirected are replaced by their target. Invalid URLs are
+    /// kept as-is for the reason they are skipped to be recorded.
+    /// URLs are saved in the frontier, see `db::frontier::push`.
+    pub fn queue_url(&self, entries: Vec<FrontierEntry>) {
+        let entries = entries.into_iter()
+            .map(|entry| {
+                let url = normalize_str(&entry.url).unwrap_or(entry.url);
+                let url = match db::redirects::resolve(url.clone()) {
+                    Ok(target) => target,
+                    Err(_) => url
+                };
+
+                FrontierEntry { url, ..entry }
+            })
+            .collect::<Vec<FrontierEntry>>();
+
+        if let Err(err) = db::frontier::push(&entries) {
+            println!("[QUEUE] Failed to queue {} URLs: {err}", entries.len());
+        }
     }
 
-    /// Starts parallel indexing.
+    /// Starts parallel indexing. URLs are taken from the frontier, requests to
+    /// a host are spaced by its crawl delay, URLs of other hosts are crawled in
+    /// the meantime.
     pub fn thread_bot(&self) {
         let is_paused_clone = self.is_paused.clone();
-        let pool_clone = self.data_pool.clone();
+        let delays_clone = self.host_delays.clone();
 
         thread::spawn(move || {
             let rt = Runtime::new().unwrap();
             let mut time_gatherer = { ifcfg!("debug", TimingGatherer::init()) };
 
             loop { 
-                let mut guard = pool_clone.lock().unwrap();
-                let url = guard.get_next();
-
-                std::mem::drop(guard);
-
-                ifcfg!("debug", time_gatherer.start_gathering());
                 if *is_paused_clone.lock().unwrap() {
                     println!("QueueBot paused for 5 more seconds...");
                     thread::sleep(Duration::from_secs(5));
                     continue;
                 }
 
-                if let Some(u) = url {
-                    rt.block_on(async {
-                        println!("Indexing: {u}");
-                        let msg = match index_url(u.clone()).await {
-                            Ok(_) => {
-                                unsafe { INDEXED_URLS_NB += 1; };
+                let now = Instant::now();
+                let mut delays = delays_clone.lock().unwrap();
+
+                delays.retain(|_, at| *at > now);
+
+                let delayed_hosts = delays.keys().cloned().collect::<Vec<_>>();
+                let host_ready_at = delays.values().min().copied();
+
+                std::mem::drop(delays);
+
+                let entry = match db::frontier::get_next(&delayed_hosts) {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        println!("[QUEUE] Failed to get the next URL: {err}");
+                        None
+                    }
+                };
+
+                // Every host is waiting, or there is no URL to crawl yet.
+                let Some(entry) = entry else {
+                    let mut wakeup = now + QUEUE_IDLE_DELAY;
+
+                    if let Ok(Some(delay)) =
+                        db::frontier::next_scheduled_in(&delayed_hosts)
+                    {
+                        wakeup = wakeup.min(now + delay);
+                    }
+                    thread::sleep_until(
+                        host_ready_at.map_or(wakeup, |at| at.min(wakeup))
+                    );
+                    continue;
+                };
+                let u = entry.url;
+
+                ifcfg!("debug", time_gatherer.start_gathering());
+                rt.block_on(async {
+                    let delay = match check_crawlable(&u).await {
+                        Crawl::Allowed(delay) => delay,
+                        Crawl::Skipped(reason) => {
+                            let _ = db::skipped_urls::record(&u, &reason);
+                            let _ = db::frontier::mark_crawled(&u);
+                            println!("Skipped: {u} -> {reason}");
+                            return;
+                        },
+                        Crawl::Postponed(reason, delay) => {
+                            println!("Postponed: {u} -> {reason}");
+                            delay_host(&delays_clone, url_host(&u), delay);
+                            let _ = db::frontier::postpone(&u, delay);
+                            return;
+                        }
+                    };
+
+                    println!("Indexing: {u}");
+                    let msg = match index_url(u.clone(), entry.depth).await {
+                        Ok(IndexOutcome::Indexed { nofollow }) => {
+                            unsafe { INDEXED_URLS_NB += 1; };
+                            if nofollow {
+                                format!("Indexed: {u} (nofollow)")
+                            } else {
                                 format!("Indexed: {u}")
                             }
-                            Err(err) => format!("Error: {u} -> {err}")
-                        };
-                        println!("{msg}");
-                    });
-                    ifcfg!("debug", time_gatherer.action_done());
-                    ifcfg!("debug", {
-                        if time_gatherer.actions_done % 10 == 0 {
-                            time_gatherer.log_gathered_data();
                         }
-                    });
-                }
+                        Ok(IndexOutcome::Excluded(reason)) => {
+                            format!("Excluded: {u} -> {reason}")
+                        }
+                        Ok(IndexOutcome::Duplicate(canonical)) => {
+                            format!("Duplicate: {u} -> {canonical}")
+                        }
+                        Ok(IndexOutcome::Unchanged) => {
+                            format!("Unchanged: {u}")
+                        }
+                        Err(err) => format!("Error: {u} -> {err}")
+                    };
+                    println!("{msg}");
+                    if let Err(err) = db::frontier::mark_crawled(&u) {
+                        println!("[QUEUE] Failed to mark {u}: {err}");
+                    }
+                    delay_host(&delays_clone, url_host(&u), delay);
+                });
+                ifcfg!("debug", time_gatherer.action_done());
+                ifcfg!("debug", {
+                    if time_gatherer.actions_done % 10 == 0 {
+                        time_gatherer.log_gathered_data();
+                    }
+                });
             }
         });
     }
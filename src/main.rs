@@ -1,4 +1,6 @@
 #![feature(thread_sleep_until)]
+// Some items are only reached from `main`, which tests don't run.
+#![cfg_attr(test, allow(dead_code))]
 #[macro_use] extern crate rocket;
 #[macro_use] extern crate lazy_static;
 #[macro_use] extern crate rusqlite;
@@ -7,60 +9,76 @@ extern crate r2d2;
 extern crate r2d2_sqlite;
 
 
+mod analysis;
 mod sanitize;
 mod indexer;
 mod searching;
+mod ranking;
+mod snippets;
+mod tokenizer;
 mod pages;
 mod macros;
 mod db;
 mod error;
-mod data_pool;
 mod api;
 mod models;
 mod schemas;
 #[cfg(feature = "debug")] mod debug;
 
 use std::env::{self, args};
-use db::{local::{read_lines, write_lines}, sites::get_rows_number};
+use std::path::PathBuf;
+use db::{frontier::FrontierEntry, local::read_lines, sites::get_rows_number};
 use debug::routes::toggle_queue_bot;
 use maud::Markup;
 use r2d2_sqlite::SqliteConnectionManager;
 use r2d2::Pool;
 use rocket::{fairing::{Fairing, Info, Kind}, form::validate::Contains, fs::*, http::Header, serde::json::Json, Config, Request, Response};
-use searching::feeling_lucky;
+use searching::{feeling_lucky, ResultsPage, DEFAULT_PER_PAGE};
+use searching::languages::{AcceptLanguage, SearchLanguages};
 use pages::indexing::indexing_page;
 use pages::search::search_result_page;
 use pages::console::*;
 use api::get_queue::*;
 use api::ownership::*;
+use api::sitemaps::*;
 use indexer::url::QueueBot;
 use indexer::sitemaps::SitemapBot;
+use indexer::feeds::FeedBot;
 use rocket_db_pools::Database;
 
 static mut INDEXED_URLS_NB: isize = 0;
 
 lazy_static! {
+    /// Path of the index database. Tests use a database of their own, next to
+    /// the test binary, see `db::init_test_tables`.
+    static ref DB_PATH: PathBuf = if cfg!(test) {
+        env::current_exe().unwrap().with_extension("db")
+    } else {
+        "./runtime/index_db.db".into()
+    };
     static ref DB_POOL: r2d2::Pool<r2d2_sqlite::SqliteConnectionManager> = {
-        let manager = SqliteConnectionManager::file("./runtime/index_db.db")
+        let manager = SqliteConnectionManager::file(&*DB_PATH)
             .with_init(|c| c.execute_batch("
                 PRAGMA synchronous = off;
                 PRAGMA encoding = 'UTF-16';
                 PRAGMA journal_mode = WAL;
+                PRAGMA foreign_keys = ON;
             "));
         Pool::new(manager).unwrap()
     };
     static ref SITEMAP_BOT: SitemapBot = SitemapBot::init();
     static ref QUEUE_BOT: QueueBot = QueueBot::init();
+    static ref FEED_BOT: FeedBot = FeedBot::init();
 }
 
 #[derive(Database)]
 #[database("postgres")]
 struct Pg(rocket_db_pools::diesel::PgPool);
 
-struct CORS;
+struct Cors;
 
 #[rocket::async_trait]
-impl Fairing for CORS {
+impl Fairing for Cors {
     fn info(&self) -> Info {
         Info {
             name: "Add CORS headers to responses",
@@ -91,18 +109,35 @@ impl Fairing for CORS {
 
 #[get("/")]
 fn search_default_ui() -> Markup {
-    search_result_page("".into(), vec![])
+    search_result_page(
+        "".into(), None, ResultsPage::empty(1, DEFAULT_PER_PAGE)
+    )
 }
 
-#[get("/search?<q>")]
-fn search_query(q: String) -> Markup {
-    let results = feeling_lucky(q.clone());
-    search_result_page(q, results)
+#[get("/search?<q>&<page>&<per_page>&<lang>")]
+fn search_query(
+    q: String,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    lang: Option<String>,
+    accept_language: AcceptLanguage
+) -> Markup {
+    let languages = SearchLanguages::new(lang, accept_language);
+    let restrict = languages.restrict.clone();
+    let results = feeling_lucky(
+        q.clone(),
+        page.unwrap_or(1),
+        per_page.unwrap_or(DEFAULT_PER_PAGE),
+        languages
+    );
+    search_result_page(q, restrict, results)
 }
 
 #[post("/index/urls", data = "<url_list>")]
 fn index_websites(url_list: Json<Vec<String>>) -> Markup {
-    QUEUE_BOT.queue_url(url_list.0);
+    QUEUE_BOT.queue_url(
+        url_list.0.into_iter().map(FrontierEntry::seed).collect()
+    );
     indexing_page()
 }
 
@@ -122,7 +157,7 @@ async fn index_websites_from_robots(domain: String) -> Markup {
 }
 
 #[rocket::main]
-async fn main() -> () {
+async fn main() {
     let _ = dotenv_vault::dotenv();
     let cargs = args().collect::<Vec<String>>();
     let pg_figment = Config::figment().merge((
@@ -130,23 +165,44 @@ async fn main() -> () {
         env::var("PG_DIESEL_URL").expect("No Postgres URL specified.")
     ));
 
-    db::sites::init_table().expect("Failed to init 'sites' table.");
-    db::domains::init_table().expect("Failed to init 'domains' table.");
-    QUEUE_BOT.thread_bot();
-    SITEMAP_BOT.thread_bot();
+    db::init_tables();
+    db::postings::migrate_word_tables()
+        .expect("Failed to migrate word tables to 'postings'.");
     unsafe {
         INDEXED_URLS_NB = get_rows_number();
     }
-    if !cargs.contains("--no-queue-recover".to_string()) {
-        let rurls = read_lines("./runtime/queue").unwrap_or(vec![]);
+    if let Some(retention) = *db::frontier::FRONTIER_RETENTION {
+        match db::frontier::prune_crawled(retention) {
+            Ok(pruned) => println!("[QUEUE] Pruned {pruned} crawled URLs"),
+            Err(err) => println!("[QUEUE] Failed to prune crawled URLs: {err}")
+        }
+    }
+    // The queue is kept in the `frontier` table, the file it used to be saved
+    // in on exit is only read once. Its URLs were mostly found on pages, they
+    // are queued as links rather than crawled first as seeds.
+    if cargs.contains("--no-queue-recover".to_string()) {
+        let cleared = db::frontier::clear_pending().unwrap_or(0);
+
+        println!("[QUEUE] Cleared {cleared} URLs");
+    } else if let Ok(rurls) = read_lines("./runtime/queue") {
+        let rurls = rurls.into_iter()
+            .filter(|url| !url.is_empty())
+            .map(|url| FrontierEntry::link(url, 1))
+            .collect::<Vec<FrontierEntry>>();
 
         println!("[QUEUE] Recovered {} URLs", rurls.len());
         QUEUE_BOT.queue_url(rurls);
+        let _ = std::fs::remove_file("./runtime/queue");
     }
+    // The bots start once the queue is cleared or recovered, so that they
+    // don't crawl URLs that are about to be removed.
+    QUEUE_BOT.thread_bot();
+    SITEMAP_BOT.thread_bot();
+    FEED_BOT.thread_bot();
 
     let _ = rocket::custom(pg_figment)
         .attach(Pg::init())
-        .attach(CORS)
+        .attach(Cors)
         .mount("/", routes![
             index_websites, 
             search_query, 
@@ -162,11 +218,9 @@ async fn main() -> () {
             get_domain_ownership_key,
             check_domain_ownership,
             get_domain_analytics,
-            get_domain_analytics_preflight
+            get_domain_analytics_preflight,
+            get_domain_sitemaps
         ])
         .launch()
         .await;
-
-    write_lines("./runtime/queue", QUEUE_BOT.get_remaining_urls())
-        .expect("werr");
 }
@@ -1,16 +1,17 @@
 use maud::{Markup, DOCTYPE};
-use crate::INDEXED_URLS_NB;
+use crate::{searching::ResultsPage, INDEXED_URLS_NB};
 
 /// Renders the search result page. To avoid too much logic overhead, we
 /// consider an empty query string as being set to print the Joogle's welcome
-/// page.
+/// page. `lang` is the language results are restricted to, if any.
 pub fn search_result_page(
     query: String, 
-    res: Vec<(String, String, String)>
+    lang: Option<String>,
+    res: ResultsPage
 ) -> Markup {
     let is_dummy = query.is_empty();
 
-    return html! {
+    html! {
         html {
             (DOCTYPE)
             head {
@@ -28,7 +29,7 @@ pub fn search_result_page(
                 @if is_dummy {
                     (welcome())
                 } @else {
-                    (results(query, res))
+                    (results(query, lang, res))
                 }
             }
         }
@@ -56,7 +57,25 @@ fn welcome() -> Markup {
     }
 }
 
-fn results(query: String, res: Vec<(String, String, String)>) -> Markup {
+/// Returns the URL of another page of results for the same query.
+fn page_url(
+    query: &str,
+    lang: &Option<String>,
+    page: usize,
+    per_page: usize
+) -> String {
+    let lang = match lang {
+        Some(lang) => format!("&lang={}", url_escape::encode_component(lang)),
+        None => String::new()
+    };
+
+    format!(
+        "/search?q={}&page={page}&per_page={per_page}{lang}",
+        url_escape::encode_component(query)
+    )
+}
+
+fn results(query: String, lang: Option<String>, res: ResultsPage) -> Markup {
     html! {
         header {
             p class="logo_like" { "JOOGLE" }
@@ -65,14 +84,47 @@ fn results(query: String, res: Vec<(String, String, String)>) -> Markup {
                     value=(query)
                     name="q"
                     placeholder="Go on, search...";
+                @if let Some(lang) = &lang {
+                    input type="hidden" name="lang" value=(lang);
+                }
                 input type="submit" value="GO" hidden;
             }
         }
         div class="results_content" {
-            @for result in res {
+            p class="results_count" {
+                (res.total) @if res.partial { "+" } " results"
+                @if res.total > 0 {
+                    " - page " (res.page) " of " (res.pages_count())
+                }
+            }
+            @for result in &res.results {
                 div {
-                    a href=(result.0) { (result.1) }
-                    p { (result.2) }
+                    a href=(result.url) { (result.title) }
+                    @if let Some(snippet) = &result.snippet {
+                        p {
+                            @for (text, highlighted) in snippet {
+                                @if *highlighted {
+                                    mark { (text) }
+                                } @else {
+                                    (text)
+                                }
+                            }
+                        }
+                    } @else {
+                        p { (result.description) }
+                    }
+                }
+            }
+            nav class="pagination" {
+                @if res.has_previous() {
+                    a href=(page_url(&query, &lang, res.page - 1, res.per_page)) {
+                        "Previous"
+                    }
+                }
+                @if res.has_next() {
+                    a href=(page_url(&query, &lang, res.page + 1, res.per_page)) {
+                        "Next"
+                    }
                 }
             }
         }
@@ -0,0 +1,152 @@
+use std::env;
+use std::str::FromStr;
+
+lazy_static! {
+    pub static ref BM25_PARAMS: Bm25Params = Bm25Params::from_env();
+}
+
+/// Parameters of the BM25 ranking function. Every field of a page has its own
+/// weight, the frequency of a term on a page is the weighted sum of its
+/// frequency in each field.
+/// Each parameter can be tuned with an environment variable, read the README to
+/// learn more.
+/// INFO: Scores are computed by the database, check `searching::feeling_lucky`
+/// to see how those parameters are used.
+#[derive(Debug, Clone)]
+pub struct Bm25Params {
+    /// Term frequency saturation.
+    pub k1: f64,
+    /// Document length normalization, from 0 (none) to 1 (full).
+    pub b: f64,
+    pub title_weight: f64,
+    pub description_weight: f64,
+    pub heading_weight: f64,
+    pub body_weight: f64,
+    /// Boost given to pages where words of the query are close to each other.
+    pub proximity_weight: f64,
+    /// Boost given to pages in the languages preferred by the user.
+    pub language_weight: f64
+}
+
+impl Default for Bm25Params {
+    fn default() -> Self {
+        Self {
+            k1: 1.2,
+            b: 0.75,
+            title_weight: 5.0,
+            description_weight: 3.0,
+            heading_weight: 2.0,
+            body_weight: 1.0,
+            proximity_weight: 1.0,
+            language_weight: 1.0
+        }
+    }
+}
+
+/// Reads a parameter from the environment, the default value is used when the
+/// variable is missing or invalid.
+fn env_param<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+impl Bm25Params {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            k1: env_param("BM25_K1", default.k1),
+            b: env_param("BM25_B", default.b),
+            title_weight: env_param("BM25_TITLE_WEIGHT", default.title_weight),
+            description_weight: env_param(
+                "BM25_DESCRIPTION_WEIGHT", default.description_weight
+            ),
+            heading_weight: env_param(
+                "BM25_HEADING_WEIGHT", default.heading_weight
+            ),
+            body_weight: env_param("BM25_BODY_WEIGHT", default.body_weight),
+            proximity_weight: env_param(
+                "PROXIMITY_WEIGHT", default.proximity_weight
+            ),
+            language_weight: env_param(
+                "LANGUAGE_WEIGHT", default.language_weight
+            )
+        }
+    }
+
+    /// Inverse document frequency of a term found on `df` of the `documents`
+    /// indexed. This variant never goes below 0.
+    pub fn idf(&self, documents: i64, df: i64) -> f64 {
+        let documents = documents.max(df) as f64;
+        let df = df as f64;
+
+        (1.0 + (documents - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    /// Multiplier of the score of a page where the words of the query are found
+    /// in a passage spanning `span` positions. Adjacent words get the highest
+    /// boost.
+    pub fn proximity_boost(&self, words: usize, span: u32) -> f64 {
+        if words < 2 {
+            return 1.0;
+        }
+        let closest = (words - 1) as f64;
+
+        1.0 + self.proximity_weight * closest / (span as f64).max(closest)
+    }
+}
+
+/// Returns true if the words of a phrase follow each other, each word is given
+/// by its sorted positions. Words given as `None` are stopwords, those can be
+/// at any position.
+pub fn has_phrase(phrase: &[Option<&Vec<u32>>]) -> bool {
+    let Some((anchor, first)) = phrase.iter()
+        .enumerate()
+        .find_map(|(i, p)| p.map(|p| (i as u32, p)))
+    else {
+        return false;
+    };
+
+    first.iter().filter(|start| **start >= anchor).any(|start| {
+        phrase.iter()
+            .enumerate()
+            .all(|(i, p)| p.is_none_or(|p| {
+                p.binary_search(&(start - anchor + i as u32)).is_ok()
+            }))
+    })
+}
+
+/// Returns the smallest span of positions containing every word, each word is
+/// given by its sorted positions.
+pub fn min_span(words: &[&Vec<u32>]) -> Option<u32> {
+    if words.is_empty() || words.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+
+    let mut merged = words.iter()
+        .enumerate()
+        .flat_map(|(w, p)| p.iter().map(move |pos| (*pos, w)))
+        .collect::<Vec<(u32, usize)>>();
+    let mut counts = vec![0; words.len()];
+    let mut covered = 0;
+    let mut start = 0;
+    let mut best: Option<u32> = None;
+
+    merged.sort();
+    for end in 0..merged.len() {
+        if counts[merged[end].1] == 0 {
+            covered += 1;
+        }
+        counts[merged[end].1] += 1;
+        while covered == words.len() {
+            let span = merged[end].0 - merged[start].0;
+
+            best = Some(best.map_or(span, |b| b.min(span)));
+            counts[merged[start].1] -= 1;
+            if counts[merged[start].1] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+    best
+}
@@ -1,4 +1,7 @@
+// Generated modules are named after their schema.
+#[allow(clippy::module_inception)]
 mod auth;
+#[allow(clippy::module_inception)]
 mod public;
 
 pub use self::public::public as _public;
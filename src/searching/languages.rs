@@ -0,0 +1,231 @@
+use rocket::{request::{FromRequest, Outcome}, Request};
+use rusqlite::types::Value;
+use crate::analysis::primary_language;
+use crate::ranking::BM25_PARAMS;
+
+/// Share of the boost kept when a page is in an accepted language but not in
+/// the accepted region, a page in `fr` or `fr-FR` for a user accepting `fr-CA`.
+const REGION_FALLBACK: f64 = 0.8;
+
+/// Languages accepted by the client with their quality, from the most to the
+/// least preferred. Tags are lowercased.
+/// INFO: This is a request guard reading the `Accept-Language` header, it never
+/// fails as a missing or invalid header gives no languages.
+pub struct AcceptLanguage(pub Vec<(String, f64)>);
+
+/// Returns true if a language tag looks like `fr` or `fr-ca`.
+pub fn is_language_tag(tag: &str) -> bool {
+    let primary = primary_language(tag);
+
+    (2..=3).contains(&primary.len()) &&
+        primary.chars().all(|c| c.is_ascii_alphabetic()) &&
+        tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Parses an `Accept-Language` header, such as `fr-CA,fr;q=0.9,en;q=0.5`.
+/// The wildcard and languages with a quality of 0 are left out.
+pub fn parse_accept_language(header: &str) -> Vec<(String, f64)> {
+    let mut languages = header.split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let tag = params.next()?.trim().to_lowercase().replace('_', "-");
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f64>().ok())
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+
+            if is_language_tag(&tag) && quality > 0.0 {
+                Some((tag, quality))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<(String, f64)>>();
+
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptLanguage {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = req.headers().get_one("Accept-Language").unwrap_or("");
+
+        Outcome::Success(Self(parse_accept_language(header)))
+    }
+}
+
+/// Languages of a search. Results are restricted to the language of `restrict`
+/// when it's set, pages in the exact locale coming first. Otherwise pages in
+/// the `preferred` languages are boosted.
+pub struct SearchLanguages {
+    pub restrict: Option<String>,
+    pub preferred: Vec<(String, f64)>
+}
+
+impl SearchLanguages {
+    /// Languages of a search for the `lang` parameter of a request, or the
+    /// languages accepted by the client if it's missing or invalid.
+    pub fn new(lang: Option<String>, accepted: AcceptLanguage) -> Self {
+        let lang = lang
+            .map(|l| l.trim().to_lowercase().replace('_', "-"))
+            .filter(|l| is_language_tag(l));
+
+        match lang {
+            Some(lang) => Self {
+                preferred: vec![(lang.clone(), 1.0)],
+                restrict: Some(lang)
+            },
+            None => Self { restrict: None, preferred: accepted.0 }
+        }
+    }
+
+    /// Returns the language queries should be analyzed in, if any.
+    pub fn query_language(&self) -> Option<String> {
+        self.preferred.first().map(|(tag, _)| primary_language(tag))
+    }
+
+    /// Returns the SQL expression of the boost of a site in the `sites` table,
+    /// from 1 for sites in none of the preferred languages to
+    /// `1 + LANGUAGE_WEIGHT` for sites in the most preferred one. Values of the
+    /// expression are pushed to `bindings`.
+    pub fn boost_sql(&self, bindings: &mut Vec<Value>) -> String {
+        // Each case is a tag, whether the locale of a site must be exactly the
+        // tag or only its language, and its weight.
+        let mut cases: Vec<(bool, String, f64)> = vec![];
+
+        for (tag, quality) in &self.preferred {
+            let primary = primary_language(tag);
+
+            if primary != *tag {
+                cases.push((true, tag.clone(), *quality));
+                cases.push((false, primary, quality * REGION_FALLBACK));
+            } else {
+                cases.push((false, primary, *quality));
+            }
+        }
+        if cases.is_empty() {
+            return "1.0".into();
+        }
+        // The first matching case is used, the best ones are checked first.
+        cases.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let mut bind = |value: Value| {
+            bindings.push(value);
+            format!("?{}", bindings.len())
+        };
+        let loc = "lower(COALESCE(sites.loc, ''))";
+        let whens = cases.into_iter()
+            .map(|(exact, tag, weight)| {
+                let tag = bind(Value::Text(tag));
+                let weight = bind(Value::Real(weight));
+
+                if exact {
+                    format!("WHEN {loc} = {tag} THEN {weight}")
+                } else {
+                    format!("
+                        WHEN {loc} = {tag} OR
+                            substr({loc}, 1, length({tag}) + 1) = {tag} || '-'
+                        THEN {weight}
+                    ")
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        let language_weight = bind(Value::Real(BM25_PARAMS.language_weight));
+
+        format!("(1.0 + {language_weight} * CASE {whens} ELSE 0.0 END)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{params_from_iter, Connection};
+
+    fn languages(lang: Option<&str>, header: &str) -> SearchLanguages {
+        SearchLanguages::new(
+            lang.map(String::from),
+            AcceptLanguage(parse_accept_language(header))
+        )
+    }
+
+    /// Returns the boost of a site in `loc` for the given languages.
+    fn boost(languages: &SearchLanguages, loc: Option<&str>) -> f64 {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut bindings = vec![];
+        let sql = languages.boost_sql(&mut bindings);
+
+        conn.execute("CREATE TABLE sites (loc TEXT)", []).unwrap();
+        conn.execute("INSERT INTO sites VALUES (?1)", [loc]).unwrap();
+        conn.query_row(
+            &format!("SELECT {sql} FROM sites"),
+            params_from_iter(bindings),
+            |row| row.get(0)
+        ).unwrap()
+    }
+
+    #[test]
+    fn languages_are_sorted_by_quality() {
+        assert_eq!(
+            parse_accept_language("en;q=0.5, fr-CA , fr;q=0.9"),
+            [("fr-ca".into(), 1.0), ("fr".into(), 0.9), ("en".into(), 0.5)]
+        );
+        assert_eq!(
+            parse_accept_language("de;q=2, es;q=0.3;level=1"),
+            [("de".into(), 1.0), ("es".into(), 0.3)]
+        );
+        assert_eq!(parse_accept_language(""), []);
+    }
+
+    #[test]
+    fn wildcards_and_refused_languages_are_left_out() {
+        assert_eq!(
+            parse_accept_language("*;q=0.1, fr, en;q=0, de;q=0.000"),
+            [("fr".into(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn malformed_entries_are_skipped() {
+        assert_eq!(
+            parse_accept_language("fr;q=abc, x, 12, en us, ;q=0.5, pt_BR;q=.7"),
+            [("fr".into(), 1.0), ("pt-br".into(), 0.7)]
+        );
+    }
+
+    #[test]
+    fn lang_parameter_overrides_the_header() {
+        let search = languages(Some("DE_at"), "fr-CA,fr;q=0.9");
+        assert_eq!(search.restrict.as_deref(), Some("de-at"));
+        assert_eq!(search.preferred, [("de-at".into(), 1.0)]);
+        assert_eq!(search.query_language().as_deref(), Some("de"));
+
+        let search = languages(Some("not a language"), "fr-CA,fr;q=0.9");
+        assert_eq!(search.restrict, None);
+        assert_eq!(search.query_language().as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn preferred_languages_are_boosted() {
+        let weight = BM25_PARAMS.language_weight;
+        let search = languages(None, "fr-CA, en;q=0.5");
+
+        assert_eq!(boost(&search, Some("fr-CA")), 1.0 + weight);
+        assert_eq!(
+            boost(&search, Some("fr")),
+            1.0 + weight * REGION_FALLBACK
+        );
+        assert_eq!(
+            boost(&search, Some("fr-FR")),
+            1.0 + weight * REGION_FALLBACK
+        );
+        assert_eq!(boost(&search, Some("en-GB")), 1.0 + weight * 0.5);
+        assert_eq!(boost(&search, Some("fro")), 1.0);
+        assert_eq!(boost(&search, None), 1.0);
+        assert_eq!(boost(&languages(None, "*"), Some("fr")), 1.0);
+    }
+}
@@ -0,0 +1,684 @@
+pub mod languages;
+pub mod query;
+
+use std::collections::HashMap;
+use rusqlite::{params_from_iter, types::Value, Connection, OptionalExtension};
+use crate::analysis::{primary_language, Analyzer};
+use crate::db::{corpus, postings::decode_positions};
+use crate::ranking::{has_phrase, min_span, BM25_PARAMS};
+use crate::snippets::{best_snippet, Snippet};
+use crate::DB_POOL;
+use self::languages::SearchLanguages;
+use self::query::{parse_query, Field, Node, Occur, Query};
+
+pub const DEFAULT_PER_PAGE: usize = 10;
+pub const MAX_PER_PAGE: usize = 100;
+/// Number of best results re-ranked with the positions of the query words.
+pub const RERANK_DEPTH: usize = 500;
+
+/// A result of a search. The snippet is the passage of the page that best
+/// matches the query, if any.
+pub struct SearchResult {
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub snippet: Option<Snippet>
+}
+
+/// A page of search results, `total` is the number of results of the query
+/// across every page. It's only a lower bound when `partial` is set: phrases
+/// are only looked for in the `RERANK_DEPTH` best results, more pages may
+/// contain them.
+pub struct ResultsPage {
+    pub results: Vec<SearchResult>,
+    pub total: usize,
+    pub partial: bool,
+    pub page: usize,
+    pub per_page: usize
+}
+
+impl ResultsPage {
+    pub fn empty(page: usize, per_page: usize) -> Self {
+        Self { results: vec![], total: 0, partial: false, page, per_page }
+    }
+
+    pub fn pages_count(&self) -> usize {
+        self.total.div_ceil(self.per_page)
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.page > 1
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.page < self.pages_count()
+    }
+}
+
+/// A word of the query found in the index, with its `idf`.
+#[derive(Clone)]
+struct QueryTerm {
+    word: String,
+    id: i64,
+    idf: f64
+}
+
+/// Returns the words of the query found in the index. The `idf` of each term is
+/// computed beforehand as it's the same for every site.
+fn get_query_terms(conn: &Connection, words: &[String]) -> Vec<QueryTerm> {
+    let bm25 = &*BM25_PARAMS;
+    let (documents, _) = corpus::get_stats(conn).unwrap();
+
+    words.iter()
+        .filter_map(|w| {
+            conn.query_row(
+                "SELECT id, df FROM terms WHERE term = ?1",
+                params![w],
+                |row| Ok(QueryTerm {
+                    word: w.clone(),
+                    id: row.get::<usize, i64>(0)?,
+                    idf: bm25.idf(documents, row.get::<usize, i64>(1)?)
+                })
+            )
+            .optional()
+            .unwrap()
+        })
+        .collect()
+}
+
+/// Fields of a site the field filters of a query are matched against.
+struct SiteFields {
+    domain: String,
+    title: String,
+    url: String,
+    loc: String
+}
+
+/// A parsed query with its words found in the index, and the languages it's
+/// searched in.
+struct Search {
+    query: Query,
+    languages: SearchLanguages,
+    /// Terms sites are ranked with, excluded words are left out.
+    ranked: Vec<QueryTerm>,
+    /// Every word of the query found in the index.
+    terms: Vec<QueryTerm>
+}
+
+/// SQL queries ranking sites are made of a `WITH` clause listing query terms,
+/// a condition on the `sites` table matching the clauses of the query, and the
+/// boost of sites in the preferred languages.
+/// Numbered parameters from 1 to 9 are reserved for ranking and paging, query
+/// terms are bound after them, then the values of the condition and boost.
+struct RankingSql {
+    cte: String,
+    filter: String,
+    boost: String,
+    bindings: Vec<Value>
+}
+
+/// Returns the SQL condition of a node of the query, `terms` are every word of
+/// the query found in the index. Values of the condition are pushed to
+/// `bindings`.
+/// INFO: Phrases can't be matched by the database, only their words are. The
+/// condition of a node holding a phrase is thus less strict than the node, the
+/// node is checked afterwards by `node_matches`.
+fn node_sql(
+    node: &Node,
+    terms: &[QueryTerm],
+    bindings: &mut Vec<Value>
+) -> String {
+    let mut bind = |value: Value| {
+        bindings.push(value);
+        format!("?{}", bindings.len())
+    };
+
+    match node {
+        Node::Term(word) => match terms.iter().find(|t| &t.word == word) {
+            Some(term) => format!("
+                EXISTS (
+                    SELECT 1 FROM postings AS p
+                    WHERE p.site_id = sites.id AND p.term_id = {}
+                )
+            ", bind(Value::Integer(term.id))),
+            None => "0".into()
+        },
+        Node::Phrase(words) => words.iter()
+            .flatten()
+            .map(|w| node_sql(&Node::Term(w.clone()), terms, bindings))
+            .collect::<Vec<String>>()
+            .join(" AND "),
+        Node::Field(field, value) => {
+            let value = bind(Value::Text(value.clone()));
+
+            match field {
+                Field::Site => format!("(
+                    COALESCE(sites.domain, '') = {value} OR
+                    substr(COALESCE(sites.domain, ''), -length({value}) - 1)
+                        = '.' || {value}
+                )"),
+                Field::InTitle => format!(
+                    "instr(lower(COALESCE(sites.title, '')), {value}) > 0"
+                ),
+                Field::InUrl => format!("instr(lower(sites.url), {value}) > 0"),
+                Field::Lang => format!("(
+                    lower(COALESCE(sites.loc, '')) = {value} OR
+                    substr(lower(COALESCE(sites.loc, '')), 1, length({value}) + 1)
+                        = {value} || '-'
+                )")
+            }
+        },
+        Node::Or(nodes) => format!("({})", nodes.iter()
+            .map(|n| format!("({})", node_sql(n, terms, bindings)))
+            .collect::<Vec<String>>()
+            .join(" OR "))
+    }
+}
+
+/// Builds the parts of the SQL queries ranking sites, see `RankingSql`.
+fn ranking_sql(
+    conn: &Connection,
+    search: &Search,
+    limit: usize,
+    offset: usize
+) -> RankingSql {
+    let Search { query, languages, ranked, terms } = search;
+    let bm25 = &*BM25_PARAMS;
+    let (_, avg_length) = corpus::get_stats(conn).unwrap();
+    let values = (0..ranked.len())
+        .map(|i| format!("(?{}, ?{})", 10 + i * 2, 11 + i * 2))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut bindings = vec![
+        Value::Real(bm25.title_weight),
+        Value::Real(bm25.description_weight),
+        Value::Real(bm25.heading_weight),
+        Value::Real(bm25.body_weight),
+        Value::Real(bm25.k1),
+        Value::Real(bm25.b),
+        Value::Real(avg_length.max(1.0)),
+        Value::Integer(limit as i64),
+        Value::Integer(offset as i64)
+    ];
+
+    for term in ranked {
+        bindings.push(Value::Integer(term.id));
+        bindings.push(Value::Real(term.idf));
+    }
+
+    let conditions = query.clauses.iter()
+        .filter_map(|(occur, node)| match occur {
+            Occur::Should => None,
+            Occur::Must => Some(node_sql(node, terms, &mut bindings)),
+            // The absence of the words of a phrase is too strict, excluded
+            // phrases are only checked by `node_matches`.
+            Occur::MustNot if node.has_phrase() => None,
+            Occur::MustNot => Some(
+                format!("NOT ({})", node_sql(node, terms, &mut bindings))
+            )
+        })
+        .map(|condition| format!("({condition})"))
+        .collect::<Vec<String>>();
+
+    RankingSql {
+        cte: if ranked.is_empty() {
+            String::new()
+        } else {
+            format!("WITH query_terms (term_id, idf) AS (VALUES {values})")
+        },
+        filter: if conditions.is_empty() {
+            "1".into()
+        } else {
+            conditions.join(" AND ")
+        },
+        boost: languages.boost_sql(&mut bindings),
+        bindings
+    }
+}
+
+/// Returns the number of sites matching the query. If the query has words to
+/// rank sites with, at least one of those must be found on a site.
+fn count_sites(conn: &Connection, search: &Search) -> usize {
+    let sql = ranking_sql(conn, search, 0, 0);
+    let RankingSql { cte, filter, .. } = &sql;
+    let select = if search.ranked.is_empty() {
+        format!("
+            SELECT COUNT(DISTINCT COALESCE(sites.cluster, sites.url))
+            FROM sites
+            WHERE {filter}
+        ")
+    } else {
+        format!("
+            {cte}
+            SELECT COUNT(DISTINCT COALESCE(sites.cluster, sites.url))
+            FROM query_terms
+            INNER JOIN postings ON postings.term_id = query_terms.term_id
+            INNER JOIN sites ON sites.id = postings.site_id
+            WHERE {filter}
+        ")
+    };
+
+    let mut select = conn.prepare(&select).unwrap();
+    // The boost isn't needed to count sites, its values are bound last and
+    // are left out.
+    let bindings = sql.bindings.iter().take(select.parameter_count());
+
+    select
+        .query_row(params_from_iter(bindings), |row| row.get::<usize, i64>(0))
+        .unwrap() as usize
+}
+
+/// Returns the ids and scores of a range of the best sites matching the query.
+/// The score of a site is the sum of the BM25 scores of every ranked term found
+/// on it, sites with the same score are ordered by id to keep pages stable.
+/// If the query has no words to rank sites with, sites are ordered by TTR.
+/// INFO: The TTR isn't part of BM25 scores, it's close for most pages and would
+/// only add noise to the ranking.
+/// Scores are boosted for sites in the preferred languages of the search.
+/// INFO: Versions of a page in different languages share a cluster, only the
+/// version in the most preferred language is kept, with the best score of its
+/// cluster.
+fn rank_sites(
+    conn: &Connection,
+    search: &Search,
+    limit: usize,
+    offset: usize
+) -> Vec<(i64, f64)> {
+    let sql = ranking_sql(conn, search, limit, offset);
+    let RankingSql { cte, filter, boost, .. } = &sql;
+    let (cte, scored) = if search.ranked.is_empty() {
+        (String::from("WITH"), format!("
+            SELECT
+                sites.id,
+                COALESCE(sites.cluster, sites.url) AS cluster,
+                {boost} AS language,
+                COALESCE(sites.ttr, 0.0) * {boost} AS score
+            FROM sites
+            WHERE {filter}
+        "))
+    } else {
+        (format!("
+            {cte},
+            matches AS (
+                SELECT
+                    postings.site_id,
+                    query_terms.idf,
+                    ?1 * postings.tf_title +
+                        ?2 * postings.tf_description +
+                        ?3 * postings.tf_heading +
+                        ?4 * postings.tf_body AS tf
+                FROM query_terms
+                INNER JOIN postings ON postings.term_id = query_terms.term_id
+            ),
+        "), format!("
+            SELECT 
+                sites.id,
+                COALESCE(sites.cluster, sites.url) AS cluster,
+                {boost} AS language,
+                {boost} * SUM(
+                    matches.idf * matches.tf * (?5 + 1.0) / (
+                        matches.tf + ?5 * (1.0 - ?6 + ?6 * sites.length / ?7)
+                    )
+                ) AS score
+            FROM matches
+            INNER JOIN sites ON sites.id = matches.site_id
+            WHERE {filter}
+            GROUP BY sites.id
+        "))
+    };
+    let mut select = conn.prepare(&format!("
+        {cte}
+        scored AS ({scored}),
+        clustered AS (
+            SELECT
+                id,
+                MAX(score) OVER (PARTITION BY cluster) AS score,
+                ROW_NUMBER() OVER (
+                    PARTITION BY cluster
+                    ORDER BY language DESC, score DESC, id
+                ) AS version
+            FROM scored
+        )
+        SELECT id, score
+        FROM clustered
+        WHERE version = 1
+        ORDER BY score DESC, id
+        LIMIT ?8 OFFSET ?9
+    ")).unwrap();
+
+    select
+        .query_map(params_from_iter(sql.bindings.iter()), |row| Ok((
+            row.get::<usize, i64>(0).unwrap(),
+            row.get::<usize, Option<f64>>(1).unwrap().unwrap_or(0.0)
+        )))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect()
+}
+
+/// Returns true if a site matches a node of the query, `positions` are the
+/// positions of the words of the query found on the site.
+fn node_matches(
+    node: &Node,
+    positions: &HashMap<&str, &Vec<u32>>,
+    site: &SiteFields
+) -> bool {
+    match node {
+        Node::Term(word) => positions.contains_key(word.as_str()),
+        Node::Phrase(words) => words.iter()
+            .map(|w| match w {
+                Some(w) => positions.get(w.as_str()).map(|p| Some(*p)),
+                None => Some(None)
+            })
+            .collect::<Option<Vec<Option<&Vec<u32>>>>>()
+            .is_some_and(|p| has_phrase(&p)),
+        Node::Field(field, value) => match field {
+            Field::Site => {
+                site.domain == *value ||
+                    site.domain.ends_with(&format!(".{value}"))
+            },
+            Field::InTitle => site.title.to_lowercase().contains(value),
+            Field::InUrl => site.url.to_lowercase().contains(value),
+            Field::Lang => {
+                let loc = site.loc.to_lowercase();
+
+                loc == *value || loc.starts_with(&format!("{value}-"))
+            }
+        },
+        Node::Or(nodes) => nodes.iter().any(|n| node_matches(n, positions, site))
+    }
+}
+
+/// Re-ranks sites with the positions of the query words on them. Sites not
+/// matching the clauses holding phrases are removed, and sites where ranked
+/// words are close to each other are boosted.
+fn rerank(
+    conn: &Connection,
+    search: &Search,
+    candidates: Vec<(i64, f64)>
+) -> Vec<(i64, f64)> {
+    let Search { query, ranked, terms, .. } = search;
+    let bm25 = &*BM25_PARAMS;
+    let placeholders = (0..terms.len())
+        .map(|i| format!("?{}", i + 2))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut select = conn.prepare(&format!("
+        SELECT term_id, positions
+        FROM postings
+        WHERE site_id = ?1 AND term_id IN ({placeholders})
+    ")).unwrap();
+    let mut select_site = conn.prepare("
+        SELECT
+            COALESCE(domain, ''),
+            COALESCE(title, ''),
+            url,
+            COALESCE(loc, '')
+        FROM sites
+        WHERE id = ?1
+    ").unwrap();
+    let phrase_clauses = query.clauses.iter()
+        .filter(|(_, node)| node.has_phrase())
+        .collect::<Vec<&(Occur, Node)>>();
+    let mut reranked = vec![];
+
+    for (site_id, score) in candidates {
+        let mut bindings = vec![Value::Integer(site_id)];
+
+        bindings.extend(terms.iter().map(|t| Value::Integer(t.id)));
+
+        let by_term = select
+            .query_map(params_from_iter(bindings.iter()), |row| Ok((
+                row.get::<usize, i64>(0).unwrap(),
+                decode_positions(&row.get::<usize, Vec<u8>>(1).unwrap())
+            )))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect::<HashMap<i64, Vec<u32>>>();
+        let positions = terms.iter()
+            .filter_map(|t| by_term.get(&t.id).map(|p| (t.word.as_str(), p)))
+            .collect::<HashMap<&str, &Vec<u32>>>();
+
+        if !phrase_clauses.is_empty() {
+            let site = select_site.query_row(params![site_id], |row| Ok(
+                SiteFields {
+                    domain: row.get::<usize, String>(0).unwrap(),
+                    title: row.get::<usize, String>(1).unwrap(),
+                    url: row.get::<usize, String>(2).unwrap(),
+                    loc: row.get::<usize, String>(3).unwrap()
+                }
+            )).unwrap();
+            let matches = phrase_clauses.iter().all(|(occur, node)| {
+                match occur {
+                    Occur::Should => true,
+                    Occur::Must => node_matches(node, &positions, &site),
+                    Occur::MustNot => !node_matches(node, &positions, &site)
+                }
+            });
+
+            if !matches {
+                continue;
+            }
+        }
+
+        let found = ranked.iter()
+            .filter_map(|t| positions.get(t.word.as_str()).copied())
+            .collect::<Vec<&Vec<u32>>>();
+        let boost = match min_span(&found) {
+            Some(span) => bm25.proximity_boost(found.len(), span),
+            None => 1.0
+        };
+
+        reranked.push((site_id, score * boost));
+    }
+    reranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    reranked
+}
+
+/// Loads the data of sites to show them as results.
+fn load_results(
+    conn: &Connection,
+    site_ids: &[i64],
+    query: &Query,
+    words: &[String]
+) -> Vec<SearchResult> {
+    let analyzer = Analyzer::for_language(&query.language);
+    let mut select = conn.prepare("
+        SELECT url, title, description, content FROM sites WHERE id = ?1
+    ").unwrap();
+
+    site_ids.iter()
+        .map(|id| select.query_row(params![id], |row| Ok(SearchResult {
+            url: row.get::<usize, String>(0).unwrap(), 
+            title: row.get::<usize, String>(1).unwrap(),
+            description: row.get::<usize, String>(2).unwrap(),
+            snippet: best_snippet(
+                &row.get::<usize, String>(3).unwrap(), words, &analyzer
+            )
+        })).unwrap())
+        .collect()
+}
+
+/// Returns the number of results before a page, pages start at 1. Offsets too
+/// large for the database are capped, those pages are empty anyway.
+fn page_offset(page: usize, per_page: usize) -> usize {
+    (page.max(1) - 1).saturating_mul(per_page).min(i64::MAX as usize)
+}
+
+/// Find matching results for a specific query by parsing the query string,
+/// and looking at which websites matching its clauses have the best cumulative
+/// BM25 score. Only the requested page of results is returned, pages start
+/// at 1. Read `query::Query` to learn about the query language.
+/// Results are restricted to the language the search is made in if there is
+/// one, otherwise results in the languages preferred by the user are boosted.
+/// The best results are then re-ranked with the positions of words: quoted
+/// phrases must be found on a page, and pages where words of the query are
+/// close to each other are boosted. Results beyond `RERANK_DEPTH` keep their
+/// BM25 order, and phrases can't be found there.
+/// INFO: This technique is meant to change, read the README to learn more.
+pub fn feeling_lucky(
+    query: String,
+    page: usize,
+    per_page: usize,
+    languages: SearchLanguages
+) -> ResultsPage {
+    let page = page.max(1);
+    let per_page = per_page.clamp(1, MAX_PER_PAGE);
+    let offset = page_offset(page, per_page);
+    let conn = DB_POOL.clone().get().unwrap();
+    let mut query = parse_query(&query, languages.query_language().as_deref());
+
+    if let Some(lang) = &languages.restrict {
+        query.clauses.push((
+            Occur::Must, Node::Field(Field::Lang, primary_language(lang))
+        ));
+    }
+
+    let words = query.words();
+    let terms = get_query_terms(&conn, &query.all_words());
+    let ranked = terms.iter()
+        .filter(|t| words.contains(&t.word))
+        .cloned()
+        .collect::<Vec<QueryTerm>>();
+    let has_filters = query.clauses.iter()
+        .any(|(occur, _)| *occur == Occur::Must);
+
+    // Words missing from the index are ignored. A query made only of field
+    // filters lists every site matching them.
+    if ranked.is_empty() && (!words.is_empty() || !has_filters) {
+        return ResultsPage::empty(page, per_page);
+    }
+
+    let has_phrases = query.has_phrases();
+    let search = Search { query, languages, ranked, terms };
+    let needs_rerank = has_phrases || search.ranked.len() > 1;
+    let (site_ids, total, partial) = if !needs_rerank {
+        let ranked_sites = rank_sites(&conn, &search, per_page, offset);
+
+        (
+            ranked_sites.iter().map(|r| r.0).collect(),
+            count_sites(&conn, &search),
+            false
+        )
+    } else {
+        let candidates = rank_sites(&conn, &search, RERANK_DEPTH, 0);
+        let depth = candidates.len();
+        let reranked = rerank(&conn, &search, candidates);
+        let mut site_ids = reranked.iter()
+            .skip(offset)
+            .take(per_page)
+            .map(|r| r.0)
+            .collect::<Vec<i64>>();
+
+        if has_phrases {
+            // Pages past the candidates weren't searched for the phrases.
+            (site_ids, reranked.len(), depth == RERANK_DEPTH)
+        } else {
+            // Sites past the re-ranked ones are taken in their BM25 order.
+            let from = offset.max(depth);
+            let missing = per_page - site_ids.len();
+
+            if missing > 0 && depth == RERANK_DEPTH {
+                site_ids.extend(
+                    rank_sites(&conn, &search, missing, from)
+                        .iter()
+                        .map(|r| r.0)
+                );
+            }
+            (site_ids, count_sites(&conn, &search), false)
+        }
+    };
+
+    ResultsPage {
+        results: load_results(&conn, &site_ids, &search.query, &words),
+        total,
+        partial,
+        page,
+        per_page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::db::{self, init_test_tables, postings::FieldFrequencies};
+    use super::languages::AcceptLanguage;
+
+    fn search(page: usize, per_page: usize) -> ResultsPage {
+        search_query("zqxwvk", page, per_page)
+    }
+
+    fn search_query(query: &str, page: usize, per_page: usize) -> ResultsPage {
+        feeling_lucky(
+            query.into(),
+            page,
+            per_page,
+            SearchLanguages::new(None, AcceptLanguage(vec![]))
+        )
+    }
+
+    #[test]
+    fn page_offsets_dont_overflow() {
+        assert_eq!(page_offset(0, 10), 0);
+        assert_eq!(page_offset(1, 10), 0);
+        assert_eq!(page_offset(3, 10), 20);
+        assert_eq!(page_offset(usize::MAX, MAX_PER_PAGE), i64::MAX as usize);
+    }
+
+    #[test]
+    fn out_of_range_pages_are_searched() {
+        init_test_tables();
+        let url = "http://paging.test/".to_string();
+        let words = HashMap::from([(
+            "zqxwvk".to_string(),
+            (FieldFrequencies { body: 1, ..Default::default() }, vec![0])
+        )]);
+
+        db::new_test_site(&url, "", "");
+        db::postings::save_site_postings(&url, &words).unwrap();
+
+        let first = search(0, 0);
+
+        assert_eq!((first.page, first.per_page), (1, 1));
+        assert_eq!(first.results.len(), 1);
+        assert_eq!(first.results[0].url, url);
+
+        let last = search(usize::MAX, usize::MAX);
+
+        assert_eq!((last.page, last.per_page), (usize::MAX, MAX_PER_PAGE));
+        assert!(last.results.is_empty());
+    }
+
+    #[test]
+    fn phrase_totals_past_the_candidates_are_partial() {
+        init_test_tables();
+        let words = HashMap::from([
+            (
+                "zqxpa".to_string(),
+                (FieldFrequencies { body: 1, ..Default::default() }, vec![0])
+            ),
+            (
+                "zqxpb".to_string(),
+                (FieldFrequencies { body: 1, ..Default::default() }, vec![1])
+            )
+        ]);
+
+        for i in 0..=RERANK_DEPTH {
+            let url = format!("http://phrases.test/{i}");
+
+            db::new_test_site(&url, "", "");
+            db::postings::save_site_postings(&url, &words).unwrap();
+        }
+
+        let phrase = search_query("\"zqxpa zqxpb\"", 1, 10);
+
+        assert_eq!(phrase.total, RERANK_DEPTH);
+        assert!(phrase.partial);
+
+        let words = search_query("zqxpa zqxpb", 1, 10);
+
+        assert_eq!(words.total, RERANK_DEPTH + 1);
+        assert!(!words.partial);
+    }
+}
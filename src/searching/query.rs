@@ -0,0 +1,475 @@
+use crate::analysis::{Analyzer, DEFAULT_LANGUAGE};
+use super::languages::is_language_tag;
+
+/// Fields of a site that can be filtered with an operator such as `site:`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// `site:`, matches a domain and its subdomains.
+    Site,
+    /// `intitle:`, matches a part of the title.
+    InTitle,
+    /// `inurl:`, matches a part of the URL.
+    InUrl,
+    /// `lang:`, matches a language and its regional variants.
+    Lang
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "site" => Some(Self::Site),
+            "intitle" => Some(Self::InTitle),
+            "inurl" => Some(Self::InUrl),
+            "lang" => Some(Self::Lang),
+            _ => None
+        }
+    }
+}
+
+/// A node of the query tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Term(String),
+    /// Words that must follow each other, stopwords are `None` and stand for
+    /// any word.
+    Phrase(Vec<Option<String>>),
+    /// The value is lowercased.
+    Field(Field, String),
+    /// At least one of the nodes must match.
+    Or(Vec<Node>)
+}
+
+impl Node {
+    pub fn has_phrase(&self) -> bool {
+        match self {
+            Node::Phrase(_) => true,
+            Node::Or(nodes) => nodes.iter().any(|n| n.has_phrase()),
+            _ => false
+        }
+    }
+
+    /// Words of the node, in order.
+    pub fn words(&self) -> Vec<String> {
+        match self {
+            Node::Term(w) => vec![w.clone()],
+            Node::Phrase(words) => words.iter().flatten().cloned().collect(),
+            Node::Field(..) => vec![],
+            Node::Or(nodes) => nodes.iter().flat_map(|n| n.words()).collect()
+        }
+    }
+}
+
+/// How a clause affects results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Occur {
+    /// Results don't need to match the clause, it only improves their score.
+    Should,
+    /// Results must match the clause, `+term`.
+    Must,
+    /// Results must not match the clause, `-term`.
+    MustNot
+}
+
+/// A search query once parsed, as a list of clauses. The query language is:
+/// - `word`: the word should be found.
+/// - `+word`: the word must be found.
+/// - `-word`: the word must not be found.
+/// - `"some words"`: the words must be found as-is.
+/// - `a OR b`: at least one of `a` or `b` must be found if one of them is a
+///   phrase or a field filter.
+/// - `site:`, `intitle:`, `inurl:`, `lang:`: the field of the site must match
+///   the value, those can be excluded with `-`.
+///
+/// Words are analyzed in the language of the query, given by `lang:` or by the
+/// language the search is made in.
+#[derive(Debug)]
+pub struct Query {
+    pub clauses: Vec<(Occur, Node)>,
+    pub language: String
+}
+
+impl Query {
+    /// Distinct words the results are ranked with, excluded words are left
+    /// out.
+    pub fn words(&self) -> Vec<String> {
+        let mut words: Vec<String> = vec![];
+
+        self.clauses.iter()
+            .filter(|(occur, _)| *occur != Occur::MustNot)
+            .flat_map(|(_, node)| node.words())
+            .for_each(|w| if !words.contains(&w) { words.push(w) });
+        words
+    }
+
+    /// Distinct words of every clause, including excluded words.
+    pub fn all_words(&self) -> Vec<String> {
+        let mut words: Vec<String> = vec![];
+
+        self.clauses.iter()
+            .flat_map(|(_, node)| node.words())
+            .for_each(|w| if !words.contains(&w) { words.push(w) });
+        words
+    }
+
+    pub fn has_phrases(&self) -> bool {
+        self.clauses.iter().any(|(_, node)| node.has_phrase())
+    }
+
+    /// A plain keyword query, every word of the query should be found.
+    fn keywords(query: &str, language: &str) -> Self {
+        let analyzer = Analyzer::for_language(language);
+
+        Self {
+            clauses: analyzer
+                .analyze(query)
+                .into_iter()
+                .flatten()
+                .map(|w| (Occur::Should, Node::Term(w)))
+                .collect(),
+            language: analyzer.language.clone()
+        }
+    }
+}
+
+/// A part of a query string, before its words are analyzed.
+#[derive(Debug, PartialEq)]
+enum Atom {
+    /// Some text, and whether it was quoted.
+    Text(String, bool),
+    Field(Field, String)
+}
+
+/// A token of a query string.
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// An atom with its prefix, `+` or `-`, if any.
+    Atom(Option<char>, Atom),
+    Or
+}
+
+/// Reads a quoted part of a query, the opening quote is already consumed.
+fn read_quoted(
+    chars: &mut std::iter::Peekable<std::str::Chars>
+) -> Result<String, String> {
+    let mut value = String::new();
+
+    for c in chars.by_ref() {
+        if c == '"' {
+            return Ok(value);
+        }
+        value.push(c);
+    }
+    Err("unterminated quote".into())
+}
+
+fn lex(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let prefix = if c == '+' || c == '-' {
+            chars.next();
+            Some(c)
+        } else {
+            None
+        };
+
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            tokens.push(Token::Atom(
+                prefix, Atom::Text(read_quoted(&mut chars)?, true)
+            ));
+            continue;
+        }
+
+        let mut word = String::new();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '"' {
+                break;
+            }
+            word.push(c);
+            chars.next();
+        }
+
+        if word == "OR" && prefix.is_none() {
+            tokens.push(Token::Or);
+            continue;
+        }
+        if let Some((name, value)) = word.split_once(':') {
+            if let Some(field) = Field::from_name(name) {
+                let value = if value.is_empty() && chars.peek() == Some(&'"') {
+                    chars.next();
+                    read_quoted(&mut chars)?
+                } else {
+                    value.to_string()
+                };
+
+                if value.trim().is_empty() {
+                    return Err(format!("missing value for `{name}:`"));
+                }
+                tokens.push(Token::Atom(
+                    prefix,
+                    Atom::Field(field, value.trim().to_lowercase())
+                ));
+                continue;
+            }
+        }
+        if !word.is_empty() {
+            tokens.push(Token::Atom(prefix, Atom::Text(word, false)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Builds the nodes of an atom, none is returned if it contains no word once
+/// analyzed. The words of a text make a phrase if `phrase` is set, otherwise
+/// those are simple terms.
+fn atom_nodes(atom: &Atom, phrase: bool, analyzer: &Analyzer) -> Vec<Node> {
+    let text = match atom {
+        Atom::Field(field, value) => return vec![
+            Node::Field(*field, value.clone())
+        ],
+        Atom::Text(text, _) => text
+    };
+    let words = analyzer.analyze(text);
+    let found = words.iter().flatten().cloned().collect::<Vec<String>>();
+    // Stopwords at both ends of a phrase don't constrain it.
+    let first = words.iter().position(Option::is_some);
+    let last = words.iter().rposition(Option::is_some);
+
+    match (first, last, found.len()) {
+        (_, _, 0) => vec![],
+        (_, _, 1) => vec![Node::Term(found[0].clone())],
+        (Some(first), Some(last), _) if phrase => vec![
+            Node::Phrase(words[first..=last].to_vec())
+        ],
+        _ => found.into_iter().map(Node::Term).collect()
+    }
+}
+
+/// Parses a query string strictly, see `Query` for the syntax. The value of
+/// `lang:` must be a language tag.
+fn parse_strict(query: &str, language: &str) -> Result<Query, String> {
+    let tokens = lex(query)?;
+
+    for token in &tokens {
+        if let Token::Atom(_, Atom::Field(Field::Lang, value)) = token {
+            if !is_language_tag(value) {
+                return Err(format!("invalid language `{value}`"));
+            }
+        }
+    }
+
+    let language = tokens.iter()
+        .find_map(|t| match t {
+            Token::Atom(prefix, Atom::Field(Field::Lang, value))
+                if *prefix != Some('-') => Some(value.clone()),
+            _ => None
+        })
+        .unwrap_or(language.into());
+    let analyzer = Analyzer::for_language(&language);
+    let mut clauses: Vec<(Occur, Node)> = vec![];
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let Token::Atom(prefix, atom) = &tokens[i] else {
+            return Err("`OR` without a left operand".into());
+        };
+        // The words of an atom make a phrase when they can't be split in
+        // several clauses, field filters are never split.
+        let grouped = prefix.is_some() ||
+            !matches!(atom, Atom::Text(_, false)) ||
+            tokens.get(i + 1) == Some(&Token::Or);
+        let mut alternatives = atom_nodes(atom, grouped, &analyzer);
+
+        if !grouped {
+            clauses.extend(alternatives.into_iter().map(|n| (Occur::Should, n)));
+            i += 1;
+            continue;
+        }
+        while tokens.get(i + 1) == Some(&Token::Or) {
+            match tokens.get(i + 2) {
+                Some(Token::Atom(None, atom)) if prefix.is_none() => {
+                    alternatives.extend(atom_nodes(atom, true, &analyzer));
+                    i += 2;
+                }
+                _ => return Err("invalid `OR` operand".into())
+            }
+        }
+        i += 1;
+
+        let node = match alternatives.len() {
+            0 => continue,
+            1 => alternatives.remove(0),
+            _ => Node::Or(alternatives)
+        };
+        // Phrases and field filters are always required, unless excluded.
+        let constrains = node.has_phrase() || match &node {
+            Node::Field(..) => true,
+            Node::Or(nodes) => nodes.iter().any(|n| matches!(n, Node::Field(..))),
+            _ => false
+        };
+        let occur = match prefix {
+            Some('+') => Occur::Must,
+            Some(_) => Occur::MustNot,
+            None if constrains => Occur::Must,
+            None => Occur::Should
+        };
+
+        clauses.push((occur, node));
+    }
+    Ok(Query { clauses, language: analyzer.language.clone() })
+}
+
+/// Parses a query string, see `Query` for the syntax. Words are analyzed in
+/// `language` unless the query has a `lang:` filter, `DEFAULT_LANGUAGE` is
+/// used if none is given. A query that can't be parsed is handled as a plain
+/// keyword query.
+pub fn parse_query(query: &str, language: Option<&str>) -> Query {
+    let language = language.unwrap_or(DEFAULT_LANGUAGE);
+
+    match parse_strict(query, language) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            println!("[SEARCH] Invalid query, using keywords: {err}");
+            Query::keywords(query, language)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Vec<(Occur, Node)> {
+        parse_query(query, None).clauses
+    }
+
+    fn term(word: &str) -> Node {
+        Node::Term(word.into())
+    }
+
+    fn phrase(words: &[Option<&str>]) -> Node {
+        Node::Phrase(words.iter().map(|w| w.map(String::from)).collect())
+    }
+
+    #[test]
+    fn words_should_be_found() {
+        assert_eq!(parse("cat dog"), vec![
+            (Occur::Should, term("cat")),
+            (Occur::Should, term("dog"))
+        ]);
+    }
+
+    #[test]
+    fn prefixes_require_or_exclude() {
+        assert_eq!(parse("+cat -dog bird"), vec![
+            (Occur::Must, term("cat")),
+            (Occur::MustNot, term("dog")),
+            (Occur::Should, term("bird"))
+        ]);
+        assert_eq!(parse("-\"black hole\""), vec![
+            (Occur::MustNot, phrase(&[Some("black"), Some("hole")]))
+        ]);
+    }
+
+    #[test]
+    fn quotes_make_phrases() {
+        assert_eq!(parse("\"black hole\" star"), vec![
+            (Occur::Must, phrase(&[Some("black"), Some("hole")])),
+            (Occur::Should, term("star"))
+        ]);
+        // Stopwords stand for any word inside of a phrase, and are left out
+        // at its ends.
+        assert_eq!(parse("\"the hole in the wall\""), vec![
+            (Occur::Must, phrase(&[Some("hole"), None, None, Some("wall")]))
+        ]);
+        // A phrase of a single word is a plain word.
+        assert_eq!(parse("\"the cat\""), vec![(Occur::Should, term("cat"))]);
+    }
+
+    #[test]
+    fn or_groups_alternatives() {
+        assert_eq!(parse("cat OR dog bird"), vec![
+            (Occur::Should, Node::Or(vec![term("cat"), term("dog")])),
+            (Occur::Should, term("bird"))
+        ]);
+        assert_eq!(parse("\"black hole\" OR star"), vec![(
+            Occur::Must,
+            Node::Or(vec![phrase(&[Some("black"), Some("hole")]), term("star")])
+        )]);
+    }
+
+    #[test]
+    fn field_filters_are_required() {
+        assert_eq!(
+            parse("cat site:Example.com intitle:\"Black Hole\" -inurl:tag"),
+            vec![
+                (Occur::Should, term("cat")),
+                (Occur::Must, Node::Field(Field::Site, "example.com".into())),
+                (Occur::Must, Node::Field(Field::InTitle, "black hole".into())),
+                (Occur::MustNot, Node::Field(Field::InUrl, "tag".into()))
+            ]
+        );
+        assert_eq!(parse("LANG:fr chat"), vec![
+            (Occur::Must, Node::Field(Field::Lang, "fr".into())),
+            (Occur::Should, term("chat"))
+        ]);
+        assert_eq!(parse("site:a.com OR site:b.com"), vec![(
+            Occur::Must,
+            Node::Or(vec![
+                Node::Field(Field::Site, "a.com".into()),
+                Node::Field(Field::Site, "b.com".into())
+            ])
+        )]);
+    }
+
+    #[test]
+    fn unknown_fields_are_words() {
+        assert_eq!(parse("author:cat"), vec![
+            (Occur::Should, term("author:cat"))
+        ]);
+    }
+
+    #[test]
+    fn invalid_queries_are_keywords() {
+        let keywords = vec![
+            (Occur::Should, term("black")),
+            (Occur::Should, term("hole"))
+        ];
+
+        assert_eq!(parse("\"black hole"), keywords);
+        assert_eq!(parse("OR black hole"), keywords);
+        assert_eq!(parse("black hole OR"), keywords);
+        assert_eq!(parse("black OR -hole"), keywords);
+        assert_eq!(parse("+black OR hole"), keywords);
+        assert_eq!(parse("site: black hole"), vec![
+            (Occur::Should, term("site")),
+            (Occur::Should, term("black")),
+            (Occur::Should, term("hole"))
+        ]);
+    }
+
+    #[test]
+    fn stray_prefixes_are_ignored() {
+        assert_eq!(parse("black - hole +"), vec![
+            (Occur::Should, term("black")),
+            (Occur::Should, term("hole"))
+        ]);
+    }
+
+    #[test]
+    fn invalid_languages_are_ignored() {
+        assert_eq!(parse_query("lang:fr chat", None).language, "fr");
+        assert_eq!(
+            parse_query("lang:../../etc/x chat", Some("de")).language,
+            "de"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+use crate::analysis::Analyzer;
+
+/// Number of words of a snippet.
+const SNIPPET_WORDS: usize = 32;
+/// Number of words shown before the first matching word of a snippet.
+const SNIPPET_LEAD_WORDS: usize = 6;
+
+/// A snippet is a passage of a page's content split into parts, each part is
+/// flagged as highlighted if it's a word of the query.
+pub type Snippet = Vec<(String, bool)>;
+
+/// Finds the passage of a page's content that best matches the query terms.
+/// The best passage is the one containing the most distinct terms of the
+/// query, then the most occurences of those terms. The content is analyzed
+/// with the analyzer of the query for its words to match the terms.
+/// Returns `None` if no term of the query can be found in the content.
+pub fn best_snippet(
+    content: &str,
+    terms: &[String],
+    analyzer: &Analyzer
+) -> Option<Snippet> {
+    let words = analyzer.analyze_spans(content)
+        .into_iter()
+        .filter_map(|(word, range)| word.map(|w| (w, range)))
+        .collect::<Vec<(String, std::ops::Range<usize>)>>();
+    let matches = words.iter()
+        .enumerate()
+        .filter(|(_, (w, _))| terms.contains(w))
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+    let mut best: Option<(usize, (usize, usize))> = None;
+
+    // Every passage starts a few words before one of the matching words.
+    for first in matches.iter().map(|i| i.saturating_sub(SNIPPET_LEAD_WORDS)) {
+        let window = first..first + SNIPPET_WORDS;
+        let in_window = matches.iter()
+            .filter(|i| window.contains(*i))
+            .map(|i| &words[*i].0)
+            .collect::<Vec<&String>>();
+        let distinct = in_window.iter().collect::<HashSet<_>>().len();
+        let score = (distinct, in_window.len());
+
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((first, score));
+        }
+    }
+
+    let (first, _) = best?;
+    let last = (first + SNIPPET_WORDS).min(words.len()) - 1;
+    let mut snippet: Snippet = vec![];
+    let mut cursor = words[first].1.start;
+
+    if first > 0 {
+        snippet.push(("… ".into(), false));
+    }
+    for (word, range) in &words[first..=last] {
+        if terms.contains(word) && range.start < cursor {
+            // Pairs of CJK characters overlap, the highlight is extended.
+            if range.end > cursor {
+                snippet.push((content[cursor..range.end].into(), true));
+                cursor = range.end;
+            }
+        } else if terms.contains(word) {
+            snippet.push((content[cursor..range.start].into(), false));
+            snippet.push((content[range.clone()].into(), true));
+            cursor = range.end;
+        }
+    }
+    snippet.push((content[cursor..words[last].1.end].into(), false));
+    if last < words.len() - 1 {
+        snippet.push((" …".into(), false));
+    }
+    Some(snippet)
+}
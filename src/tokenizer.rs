@@ -0,0 +1,160 @@
+use std::{env, ops::Range};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+lazy_static! {
+    /// Whether accents and other diacritics are removed from words, `é` is
+    /// then indexed and searched as `e`. Enabled unless `FOLD_DIACRITICS` is
+    /// set to `0` or `false`.
+    /// WARN: Sites indexed before changing this setting have to be indexed
+    /// again, their words wouldn't match the ones of queries otherwise. Those
+    /// are indexed again when re-crawled, see `ANALYSIS_VERSION`.
+    pub static ref FOLD_DIACRITICS: bool = env::var("FOLD_DIACRITICS")
+        .map(|v| v != "0" && v.to_lowercase() != "false")
+        .unwrap_or(true);
+}
+
+/// Returns true for characters of scripts written without spaces between
+/// words: Han ideographs, Hiragana and Katakana.
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF |   // Hiragana, Katakana
+        0x31F0..=0x31FF |   // Katakana phonetic extensions
+        0x3400..=0x4DBF |   // CJK unified ideographs extension A
+        0x4E00..=0x9FFF |   // CJK unified ideographs
+        0xF900..=0xFAFF |   // CJK compatibility ideographs
+        0xFF66..=0xFF9F |   // Halfwidth Katakana
+        0x20000..=0x2FA1F   // CJK unified ideographs extensions B and after
+    )
+}
+
+/// Normalizes a word so that the same word is always written the same way:
+/// compatibility characters are replaced (NFKC) and the word is lowercased.
+fn normalize(word: &str) -> String {
+    word.nfkc().collect::<String>().to_lowercase()
+}
+
+/// Removes diacritics from a word if `FOLD_DIACRITICS` is enabled.
+/// INFO: Only the diacritics of latin, greek and cyrillic letters are removed,
+/// marks of other scripts such as devanagari vowel signs are part of words.
+pub fn fold_diacritics(word: &str) -> String {
+    if *FOLD_DIACRITICS {
+        word.nfd()
+            .filter(|c| !('\u{0300}'..='\u{036F}').contains(c))
+            .nfc()
+            .collect()
+    } else {
+        word.to_string()
+    }
+}
+
+/// Splits a text into words, each word comes with its byte range in the text
+/// to find back where it is.
+/// Words are found on Unicode word boundaries, apostrophes split words too so
+/// that `l'école` gives `l` and `ecole`. Scripts written without spaces, such
+/// as Chinese or Japanese, are split into overlapping pairs of characters,
+/// `東京都` gives `東京` and `京都`.
+/// INFO: Words are normalized but diacritics are kept, those are removed by
+/// `analysis::Analyzer` once stopwords are filtered and words are stemmed.
+pub fn tokenize_spans(text: &str) -> Vec<(String, Range<usize>)> {
+    let mut words = vec![];
+    // Characters of the current run of CJK characters.
+    let mut run: Vec<(usize, char)> = vec![];
+
+    let flush = |run: &mut Vec<(usize, char)>, words: &mut Vec<_>| {
+        let end = |(i, c): (usize, char)| i + c.len_utf8();
+
+        match run.len() {
+            0 => {},
+            1 => words.push((
+                normalize(&run[0].1.to_string()),
+                run[0].0..end(run[0])
+            )),
+            _ => words.extend(run.windows(2).map(|pair| (
+                normalize(&format!("{}{}", pair[0].1, pair[1].1)),
+                pair[0].0..end(pair[1])
+            )))
+        }
+        run.clear();
+    };
+
+    for (start, segment) in text.split_word_bound_indices() {
+        if segment.chars().all(is_cjk) {
+            if run.last().is_some_and(|&(i, c)| i + c.len_utf8() != start) {
+                flush(&mut run, &mut words);
+            }
+            run.extend(segment.char_indices().map(|(i, c)| (start + i, c)));
+            continue;
+        }
+        flush(&mut run, &mut words);
+        if !segment.chars().any(char::is_alphanumeric) {
+            continue;
+        }
+
+        let mut part_start = 0;
+        let bounds = segment.match_indices(['\'', '’'])
+            .map(|(i, m)| (i, i + m.len()))
+            .chain([(segment.len(), segment.len())]);
+
+        for (part_end, next_start) in bounds {
+            let part = &segment[part_start..part_end];
+            let word = normalize(part);
+
+            if word.chars().any(char::is_alphanumeric) {
+                words.push((word, start + part_start..start + part_end));
+            }
+            part_start = next_start;
+        }
+    }
+    flush(&mut run, &mut words);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        tokenize_spans(text).into_iter().map(|(word, _)| word).collect()
+    }
+
+    #[test]
+    fn words_are_split_on_unicode_boundaries() {
+        assert_eq!(
+            words("Hello, world! Pi is 3.14, e-mail me."),
+            ["hello", "world", "pi", "is", "3.14", "e", "mail", "me"]
+        );
+        assert_eq!(words("l'école d’été"), ["l", "école", "d", "été"]);
+        assert_eq!(words(" -- ... "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn words_are_normalized_and_lowercased() {
+        assert_eq!(words("ﬁne ＡＢＣ Straße"), ["fine", "abc", "straße"]);
+
+        let text = "ＡＢＣ Déjà";
+        let spans = tokenize_spans(text);
+        assert_eq!(spans[0], ("abc".to_string(), 0..9));
+        assert_eq!(&text[spans[1].1.clone()], "Déjà");
+    }
+
+    #[test]
+    fn diacritics_are_folded() {
+        assert_eq!(fold_diacritics("déjà-vu"), "deja-vu");
+        assert_eq!(fold_diacritics("Ünïcödé"), "Unicode");
+        assert_eq!(fold_diacritics("Ёлка"), "Елка");
+        assert_eq!(fold_diacritics("हिंदी"), "हिंदी");
+    }
+
+    #[test]
+    fn cjk_is_split_into_bigrams() {
+        assert_eq!(
+            tokenize_spans("東京都"),
+            [("東京".to_string(), 0..6), ("京都".to_string(), 3..9)]
+        );
+        assert_eq!(words("猫"), ["猫"]);
+        assert_eq!(words("東京 大阪"), ["東京", "大阪"]);
+        assert_eq!(words("東京tokyo"), ["東京", "tokyo"]);
+        assert_eq!(words("ｶﾀｶﾅ"), ["カタ", "タカ", "カナ"]);
+    }
+}